@@ -0,0 +1,129 @@
+// The context holds the state that is shared by every evaluation within a single `Miniscript` instance,
+// as opposed to the `Environment`, which only stores variables.
+
+use std::{collections::HashMap, rc::Rc};
+
+use crate::{function::Function, intrinsics, value_map::MapRef, EvalResult};
+
+pub struct Context {
+    output: Box<dyn FnMut(&str)>,
+    // Printed text that doesn't end in a newline yet.  It's held back until the rest of its line comes along.
+    pending_output: String,
+    pub types: TypeMaps,
+    // The built-in functions, by name.  A variable of the same name hides one.
+    intrinsics: HashMap<String, EvalResult>,
+    // The value of the last expression entered at the prompt, which the prompt calls `_`.  It's kept out of the
+    // variables so that it never shows up in `locals` or `globals`.
+    pub last_result: Option<EvalResult>,
+    // How many script function calls are under way, and roughly where the native stack was when the outermost one
+    // began.
+    pub call_depth: usize,
+    pub stack_base: usize,
+}
+
+impl Context {
+    pub fn new() -> Self {
+        let mut context = Self {
+            output: Box::new(|line| println!("{}", line)),
+            pending_output: String::new(),
+            types: TypeMaps::new(),
+            intrinsics: HashMap::new(),
+            last_result: None,
+            call_depth: 0,
+            stack_base: 0,
+        };
+        intrinsics::add_standard(&mut context);
+        context
+    }
+
+    // Returns the new function value, so that it can also be put into type maps.
+    pub fn add_intrinsic(&mut self, name: &str, function: Function) -> EvalResult {
+        let function = EvalResult::Function(Rc::new(function));
+        self.intrinsics.insert(name.to_string(), function.clone());
+        function
+    }
+
+    pub fn intrinsic(&self, name: &str) -> Option<EvalResult> {
+        self.intrinsics.get(name).cloned()
+    }
+
+    // Replace the destination of everything the script prints.  By default each line goes to stdout.
+    pub fn set_output(&mut self, output: impl FnMut(&str) + 'static) {
+        self.output = Box::new(output);
+    }
+
+    pub fn print(&mut self, line: &str) {
+        self.write(line, "\n");
+    }
+
+    // Print `text` followed by `delimiter` rather than a newline.  The output still receives whole lines.
+    pub fn write(&mut self, text: &str, delimiter: &str) {
+        self.pending_output.push_str(text);
+        self.pending_output.push_str(delimiter);
+        while let Some(end) = self.pending_output.find('\n') {
+            let line: String = self.pending_output.drain(..=end).collect();
+            (self.output)(&line[..end]);
+        }
+    }
+
+    // Send out the last line, if the program didn't finish it.
+    pub fn flush(&mut self) {
+        if !self.pending_output.is_empty() {
+            let line = std::mem::take(&mut self.pending_output);
+            (self.output)(&line);
+        }
+    }
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The maps that stand for the built-in types: `number`, `string`, `list`, `map` and `funcRef`.
+// Every value of a built-in type `isa` its type map.
+pub struct TypeMaps {
+    pub number: MapRef,
+    pub string: MapRef,
+    pub list: MapRef,
+    pub map: MapRef,
+    pub function: MapRef,
+}
+
+impl TypeMaps {
+    fn new() -> Self {
+        Self {
+            number: Rc::default(),
+            string: Rc::default(),
+            list: Rc::default(),
+            map: Rc::default(),
+            function: Rc::default(),
+        }
+    }
+
+    // The type map a script refers to as `name`, if there is one.
+    pub fn get(&self, name: &str) -> Option<EvalResult> {
+        let map = match name {
+            "number" => &self.number,
+            "string" => &self.string,
+            "list" => &self.list,
+            "map" => &self.map,
+            "funcRef" => &self.function,
+            _ => return None,
+        };
+        Some(EvalResult::Map(map.clone()))
+    }
+
+    // The type map of `value`.  `null` has no type map.
+    pub fn of(&self, value: &EvalResult) -> Option<&MapRef> {
+        match value {
+            EvalResult::Number(_) => Some(&self.number),
+            EvalResult::String(_) => Some(&self.string),
+            EvalResult::List(_) => Some(&self.list),
+            EvalResult::Map(_) => Some(&self.map),
+            EvalResult::Function(_) => Some(&self.function),
+            EvalResult::Null | EvalResult::Error(_) => None,
+        }
+    }
+}
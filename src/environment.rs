@@ -1,57 +1,78 @@
-// The environment stores a map of variable name to string/f64 value for global variables.
-// The environment is used to resolve variable references in the AST.
+// The environment stores the variables that are visible to the code being run.
+// MiniScript only ever looks in three places for an identifier: the locals of the running function, the locals of
+// the function it was defined in (`outer`), and the globals.  Callers in between are never searched.
+// At the global level, all three are the same scope.
+// Scripts can get at each of these as a map through the identifiers `locals`, `outer` and `globals`.
 
-use std::collections::HashMap;
-use crate::EvalResult;
+use std::{cell::RefCell, rc::Rc};
+use crate::{EvalResult, ValueMap};
+
+// A set of variables, keyed by name.  Scopes are shared, so writes made through one handle (including a script's
+// `locals` map) are seen through all of them.
+pub type Scope = Rc<RefCell<ValueMap>>;
 
 #[derive(Debug, Clone)]
 pub struct Environment {
-    pub enclosing: Box<Option<Environment>>,
-    pub variables: HashMap<String, EvalResult>,
-    // pub functions: HashMap<String, Vec<String>>,
+    pub locals: Scope,
+    pub outer: Scope,
+    pub globals: Scope,
 }
+
 impl Environment {
     pub fn new_root() -> Self {
+        let globals = Scope::default();
         Self {
-            enclosing: Box::new(None),
-            variables: HashMap::new(),
-            // functions: HashMap::new(),
+            locals: globals.clone(),
+            outer: globals.clone(),
+            globals,
         }
     }
 
-    pub fn new_child(enclosing: Environment) -> Self {
+    // The environment for a call to a function that was defined in `outer`.
+    pub fn new_call(outer: Scope, globals: Scope) -> Self {
         Self {
-            enclosing: Box::new(Some(enclosing)),
-            variables: HashMap::new(),
-            // functions: HashMap::new(),
+            locals: Scope::default(),
+            outer,
+            globals,
         }
     }
-    
+
+    // Assignment always creates or updates a local variable.
     pub fn set(&mut self, name: &str, value: &EvalResult) {
-        self.variables.insert(name.to_string(), value.clone());
-    }
-    
-    pub fn get(&self, name: &str) -> Result<&EvalResult, String> {
-        match self.variables.get(name) {
-            Some(value) => Ok(value),
-            None => {
-                match self.enclosing.as_ref() {
-                    Some(ref enclosing) => enclosing.get(name),
-                    None => Err(format!("Undefined Identifier: '{}' is unknown in this context.", name)),
-                }
-            },
+        self.locals.borrow_mut().set(EvalResult::String(name.to_string()), value.clone());
+    }
+
+    pub fn get(&self, name: &str) -> Result<EvalResult, String> {
+        match name {
+            "locals" => return Ok(EvalResult::Map(self.locals.clone())),
+            "outer" => return Ok(EvalResult::Map(self.outer.clone())),
+            "globals" => return Ok(EvalResult::Map(self.globals.clone())),
+            _ => {},
+        }
+
+        let key = EvalResult::String(name.to_string());
+        for scope in [&self.locals, &self.outer, &self.globals] {
+            if let Some(value) = scope.borrow().get(&key) {
+                return Ok(value.clone());
+            }
         }
+        Err(format!("Undefined Identifier: '{}' is unknown in this context", name))
+    }
+
+    // `locals`, `outer` and `globals` always name the scopes themselves, so they can't be assigned to.
+    pub fn is_scope_name(name: &str) -> bool {
+        matches!(name, "locals" | "outer" | "globals")
     }
-    
+
     pub fn contains(&self, name: &str) -> bool {
-        self.variables.contains_key(name)
+        self.locals.borrow().contains_key(&EvalResult::String(name.to_string()))
     }
-    
+
     pub fn remove(&mut self, name: &str) {
-        self.variables.remove(name);
+        self.locals.borrow_mut().remove(&EvalResult::String(name.to_string()));
     }
 
     pub fn clear(&mut self) {
-        self.variables.clear();
+        self.locals.borrow_mut().clear();
     }
-}
\ No newline at end of file
+}
@@ -4,7 +4,6 @@ use std::error::Error as StdError;
 #[derive(Debug, Clone, PartialEq)]
 pub struct Error {
     pub line: i64,
-    location: String,
     message: String,
     pub stage: ErrorStage,
 }
@@ -16,10 +15,9 @@ impl StdError for Error {
 }
 
 impl Error {
-    pub fn new(line: i64, location: &str, message: &str, stage: ErrorStage) -> Self {
+    pub fn new(line: i64, message: &str, stage: ErrorStage) -> Self {
         Self {
             line,
-            location: location.to_string(),
             message: message.to_string(),
             stage,
         }
@@ -28,6 +26,6 @@ impl Error {
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "[line {}] {} Error{}: {}", self.line, self.stage, self.location, self.message)
+        write!(f, "{} Error: {} [line {}]", self.stage, self.message, self.line)
     }
 }
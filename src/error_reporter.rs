@@ -1,4 +1,4 @@
-use crate::{error_stage::ErrorStage, Error, Token, TokenType};
+use crate::{context::Context, error_stage::ErrorStage, Error, Token, TokenType};
 
 pub struct ErrorReporter {
     errors: Vec<Error>,
@@ -12,24 +12,25 @@ impl ErrorReporter {
     }
 
     pub fn runtime_error(&mut self, line: i64, message: &str) -> Error {
-        self.report(line, "", message, ErrorStage::Runtime)
+        self.report(line, message, ErrorStage::Runtime)
     }
 
     pub fn error_token(&mut self, token: Token, message: &str) -> Error {
+        // Running out of source is reported past the last line, which makes it clear the problem is the end of the file.
         if token.token_type == TokenType::EOF {
-            self.report(token.line, " at end", message, ErrorStage::Compile);
+            return self.report(token.line + 1, message, ErrorStage::Compile);
         }
 
-        self.report(token.line, format!(" at '{:}'", token.lexeme).as_str(), message, ErrorStage::Compile)
+        self.report(token.line, message, ErrorStage::Compile)
     }
 
     pub fn error_line(&mut self, line: i64, message: &str) -> Error {
-        self.report(line, "", message, ErrorStage::Compile)
+        self.report(line, message, ErrorStage::Compile)
     }
 
-    fn report(&mut self, line: i64, location: &str, message: &str, stage: ErrorStage) -> Error {
+    fn report(&mut self, line: i64, message: &str, stage: ErrorStage) -> Error {
         // let already_errored = self.error_exists_for_line(line);
-        let error = Error::new(line, location, message, stage);
+        let error = Error::new(line, message, stage);
         
         // An error token will bubble to the top if there's an error and get printed in the REPL.
         // TODO: Is this what I really want though?
@@ -61,11 +62,11 @@ impl ErrorReporter {
         false
     }
 
-    pub fn dump(&self) {
+    pub fn dump(&self, context: &mut Context) {
         let mut current_line = -1;
         for error in &self.errors {
             if error.line != current_line {
-                println!("{:}", error);
+                context.print(&error.to_string());
                 current_line = error.line;
             }
         }
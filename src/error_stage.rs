@@ -9,7 +9,7 @@ pub enum ErrorStage {
 impl Display for ErrorStage {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
-            ErrorStage::Compile => write!(f, "Compile"),
+            ErrorStage::Compile => write!(f, "Compiler"),
             ErrorStage::Runtime => write!(f, "Runtime"),
         }
     }
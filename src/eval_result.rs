@@ -1,21 +1,166 @@
-use std::fmt::{Display, Formatter};
+use std::{cell::RefCell, collections::hash_map::DefaultHasher, fmt::{Display, Formatter}, hash::{Hash, Hasher}, rc::Rc};
 
-use crate::Error;
+use crate::{function::Function, value_map::ValueMap, Error};
 
-#[derive(Debug, PartialEq, Clone)]
+// How deep equality tests, hashing and formatting will follow nested lists and maps before giving up.
+// This keeps a collection that contains itself from recursing forever.
+const RECURSION_LIMIT: usize = 16;
+
+#[derive(Debug, Clone)]
 pub enum EvalResult {
     Null,
     Number(f64),
     String(String),
+    // Lists are reference types: every copy of the value shares the same elements.
+    List(Rc<RefCell<Vec<EvalResult>>>),
+    // Maps are reference types too.
+    Map(Rc<RefCell<ValueMap>>),
+    // A function is only ever equal to itself.
+    Function(Rc<Function>),
     Error(Error),
 }
 
+impl EvalResult {
+    pub fn new_list(values: Vec<EvalResult>) -> Self {
+        EvalResult::List(Rc::new(RefCell::new(values)))
+    }
+
+    pub fn new_map(map: ValueMap) -> Self {
+        EvalResult::Map(Rc::new(RefCell::new(map)))
+    }
+
+    // A hash that agrees with MiniScript equality: values that are equal always hash the same.
+    pub fn hash_code(&self) -> u64 {
+        self.hash_code_limited(RECURSION_LIMIT)
+    }
+
+    fn hash_code_limited(&self, depth: usize) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        match self {
+            EvalResult::Null => 0.hash(&mut hasher),
+            // Make sure that 0 and -0 hash the same, since they're equal.
+            EvalResult::Number(n) => (if *n == 0.0 { 0.0 } else { *n }).to_bits().hash(&mut hasher),
+            EvalResult::String(s) => s.hash(&mut hasher),
+            EvalResult::List(items) => {
+                items.borrow().len().hash(&mut hasher);
+                if depth > 0 {
+                    for item in items.borrow().iter() {
+                        item.hash_code_limited(depth - 1).hash(&mut hasher);
+                    }
+                }
+            },
+            EvalResult::Map(map) => {
+                // Equal maps may have been built in a different order, so combine the entries order-independently.
+                let map = map.borrow();
+                let mut combined = map.len() as u64;
+                if depth > 0 {
+                    for (key, value) in map.iter() {
+                        combined = combined.wrapping_add(key.hash_code_limited(depth - 1) ^ value.hash_code_limited(depth - 1).rotate_left(1));
+                    }
+                }
+                combined.hash(&mut hasher);
+            },
+            EvalResult::Function(function) => Rc::as_ptr(function).hash(&mut hasher),
+            EvalResult::Error(e) => e.to_string().hash(&mut hasher),
+        }
+        hasher.finish()
+    }
+
+    // Format the value the way it would be written in source code, e.g. strings are quoted.
+    // This is how values appear when nested inside a list or map.
+    pub fn code_form(&self) -> String {
+        self.code_form_limited(RECURSION_LIMIT)
+    }
+
+    fn code_form_limited(&self, depth: usize) -> String {
+        match self {
+            EvalResult::String(s) => format!("\"{}\"", s.replace('"', "\"\"")),
+            EvalResult::List(_) if depth == 0 => "[...]".to_string(),
+            EvalResult::List(items) => {
+                let items: Vec<String> = items.borrow().iter().map(|item| item.code_form_limited(depth - 1)).collect();
+                format!("[{}]", items.join(", "))
+            },
+            EvalResult::Map(_) if depth == 0 => "{...}".to_string(),
+            EvalResult::Map(map) => {
+                let entries: Vec<String> = map.borrow().iter()
+                    .map(|(key, value)| format!("{}: {}", key.code_form_limited(depth - 1), value.code_form_limited(depth - 1)))
+                    .collect();
+                format!("{{{}}}", entries.join(", "))
+            },
+            _ => self.to_string(),
+        }
+    }
+
+    fn equals(&self, other: &EvalResult, depth: usize) -> bool {
+        match (self, other) {
+            (EvalResult::Null, EvalResult::Null) => true,
+            (EvalResult::Number(l), EvalResult::Number(r)) => l == r,
+            (EvalResult::String(l), EvalResult::String(r)) => l == r,
+            (EvalResult::List(l), EvalResult::List(r)) => {
+                if Rc::ptr_eq(l, r) || depth == 0 {
+                    return true;
+                }
+                let (l, r) = (l.borrow(), r.borrow());
+                l.len() == r.len() && l.iter().zip(r.iter()).all(|(l, r)| l.equals(r, depth - 1))
+            },
+            (EvalResult::Map(l), EvalResult::Map(r)) => {
+                if Rc::ptr_eq(l, r) || depth == 0 {
+                    return true;
+                }
+                let (l, r) = (l.borrow(), r.borrow());
+                l.len() == r.len() && l.iter().all(|(key, value)| r.get(key).is_some_and(|other| value.equals(other, depth - 1)))
+            },
+            (EvalResult::Function(l), EvalResult::Function(r)) => Rc::ptr_eq(l, r),
+            (EvalResult::Error(l), EvalResult::Error(r)) => l == r,
+            _ => false,
+        }
+    }
+}
+
+impl PartialEq for EvalResult {
+    fn eq(&self, other: &Self) -> bool {
+        self.equals(other, RECURSION_LIMIT)
+    }
+}
+
+// Format a number the way MiniScript does: whole numbers without a decimal point, very large or small numbers like
+// "1.230000E-09", and everything else with up to six digits after the decimal point.
+pub fn format_number(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "Infinity" } else { "-Infinity" }.to_string()
+    } else if value == 0.0 {
+        // This includes -0, which the reference also prints as "0".
+        "0".to_string()
+    } else if value % 1.0 == 0.0 {
+        format!("{:.0}", value)
+    } else if !(-1e10..=1e10).contains(&value) || (value < 1e-6 && value > -1e-6) {
+        // Rust leaves out the exponent's sign and padding, e.g. "1.230000E-9", so put them back.
+        let formatted = format!("{:.6E}", value);
+        let (mantissa, exponent) = formatted.split_once('E').unwrap();
+        let exponent: i32 = exponent.parse().unwrap();
+        format!("{}E{}{:02}", mantissa, if exponent < 0 { '-' } else { '+' }, exponent.abs())
+    } else {
+        // Drop trailing zeros, but keep at least one digit after the decimal point.
+        let formatted = format!("{:.6}", value);
+        let trimmed = formatted.trim_end_matches('0');
+        if trimmed.ends_with('.') {
+            format!("{}0", trimmed)
+        } else {
+            trimmed.to_string()
+        }
+    }
+}
+
 impl Display for EvalResult {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
             EvalResult::Null => write!(f, "null"),
-            EvalResult::Number(n) => write!(f, "{}", n),
+            EvalResult::Number(n) => write!(f, "{}", format_number(*n)),
             EvalResult::String(s) => write!(f, "{}", s),
+            EvalResult::List(_) | EvalResult::Map(_) => write!(f, "{}", self.code_form()),
+            EvalResult::Function(function) => write!(f, "{}", function),
             EvalResult::Error(e) => write!(f, "{}", e),
         }
     }
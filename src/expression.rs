@@ -1,22 +1,60 @@
-use std::fmt::{Debug, Display};
+use std::{fmt::{Debug, Display}, rc::Rc};
 
-use crate::{environment::Environment, error_reporter::ErrorReporter, statement::Stmt, token::Token, Error, EvalResult, TokenType};
+use crate::{context::Context, environment::Environment, error_reporter::ErrorReporter, function::{Body, Function, Outer, Param}, span::Span, statement::Stmt, token::Token, value_map::{self, MapRef}, Error, EvalResult, TokenType, ValueMap};
 
+// Every expression ends with the span of source it was parsed from, brackets and all.
 #[derive(Clone, PartialEq)]
 pub enum Expr {
-    Binary(Box<Expr>, Token, Box<Expr>),
-    Grouping(Box<Expr>),
-    Literal(Token),
-    Unary(Token, Box<Expr>),
+    AddressOf(Token, Box<Expr>, Span),
+    Binary(Box<Expr>, Token, Box<Expr>, Span),
+    Call(Box<Expr>, Token, Vec<Expr>, Span),
+    // Two or more comparisons in a row, like `a < b <= c`.
+    Comparison(Box<Expr>, Vec<(Token, Expr)>, Span),
+    Dot(Box<Expr>, Token, Span),
+    Function(Token, Vec<(Token, Option<Expr>)>, Rc<Vec<Stmt>>, Span),
+    Grouping(Box<Expr>, Span),
+    Index(Box<Expr>, Token, Box<Expr>, Span),
+    List(Token, Vec<Expr>, Span),
+    Literal(Token, Span),
+    Map(Token, Vec<(Expr, Expr)>, Span),
+    Slice(Box<Expr>, Token, Option<Box<Expr>>, Option<Box<Expr>>, Span),
+    Unary(Token, Box<Expr>, Span),
 }
 
 impl Expr {
     pub fn line(&self) -> i64 {
         match self {
-            Expr::Binary(_, op, _) =>  op.line,
-            Expr::Grouping(expr) => expr.line(),
-            Expr::Literal(token) => token.line,
-            Expr::Unary(op, _) => op.line,
+            Expr::AddressOf(at, _, _) => at.line,
+            Expr::Binary(_, op, _, _) =>  op.line,
+            Expr::Call(_, paren, _, _) => paren.line,
+            Expr::Comparison(_, comparisons, _) => comparisons[0].0.line,
+            Expr::Dot(_, name, _) => name.line,
+            Expr::Function(keyword, _, _, _) => keyword.line,
+            Expr::Grouping(expr, _) => expr.line(),
+            Expr::Index(_, bracket, _, _) => bracket.line,
+            Expr::List(bracket, _, _) => bracket.line,
+            Expr::Literal(token, _) => token.line,
+            Expr::Map(brace, _, _) => brace.line,
+            Expr::Slice(_, bracket, _, _, _) => bracket.line,
+            Expr::Unary(op, _, _) => op.line,
+        }
+    }
+
+    pub fn span(&self) -> &Span {
+        match self {
+            Expr::AddressOf(_, _, span)
+            | Expr::Binary(_, _, _, span)
+            | Expr::Call(_, _, _, span)
+            | Expr::Comparison(_, _, span)
+            | Expr::Dot(_, _, span)
+            | Expr::Function(_, _, _, span)
+            | Expr::Grouping(_, span)
+            | Expr::Index(_, _, _, span)
+            | Expr::List(_, _, span)
+            | Expr::Literal(_, span)
+            | Expr::Map(_, _, span)
+            | Expr::Slice(_, _, _, _, span)
+            | Expr::Unary(_, _, span) => span,
         }
     }
 }
@@ -24,196 +62,851 @@ impl Expr {
 pub fn is_truthy(value: EvalResult) -> bool {
     match value {
         EvalResult::Number(value) => value != 0.0,
-        EvalResult::String(value) => value.len() > 0,
+        EvalResult::String(value) => !value.is_empty(),
+        EvalResult::List(items) => !items.borrow().is_empty(),
+        EvalResult::Map(map) => !map.borrow().is_empty(),
+        EvalResult::Function(_) => true,
         EvalResult::Null => false,
         _ => unreachable!("This shouldn't have happened."),
     }
 }
 
-pub fn is_falsy(value: EvalResult) -> bool {
-    !is_truthy(value)
+// `and`, `or` and `not` treat their operands as probabilities.  A number counts by its magnitude, clamped to 1, so
+// `-1` and `5` are both fully true.  Any other value is 1 if it's truthy and 0 if not.
+fn fuzzy_truth(value: &EvalResult) -> f64 {
+    match value {
+        EvalResult::Number(value) => value.abs().min(1.0),
+        _ => if is_truthy(value.clone()) { 1.0 } else { 0.0 },
+    }
 }
 
 pub fn format_ast(expr: &Expr) -> String {
     match expr {
-        Expr::Binary(left, operator, right) => format!("({:} {:} {:})", operator.lexeme, format_ast(left), format_ast(right)),
-        Expr::Grouping(expr) => format!("(group {:})", format_ast(expr)),
-        Expr::Literal(value) => (*value).lexeme.clone(),
-        Expr::Unary(operator, expr) => format!("({:} {:})", operator.lexeme, format_ast(expr)),
+        Expr::AddressOf(_, expr, _) => format!("(@ {:})", format_ast(expr)),
+        Expr::Binary(left, operator, right, _) => format!("({:} {:} {:})", operator.lexeme, format_ast(left), format_ast(right)),
+        Expr::Comparison(first, comparisons, _) => format!("(compare {:}{:})", format_ast(first), comparisons.iter().map(|(op, e)| format!(" {:} {:}", op.lexeme, format_ast(e))).collect::<String>()),
+        Expr::Call(callee, _, arguments, _) => format!("(call {:}{:})", format_ast(callee), arguments.iter().map(|e| format!(" {:}", format_ast(e))).collect::<String>()),
+        Expr::Dot(expr, name, _) => format!("(. {:} {:})", format_ast(expr), name.lexeme),
+        Expr::Function(_, params, body, _) => {
+            let params: Vec<String> = params.iter().map(|(name, default)| match default {
+                Some(default) => format!("{:}={:}", name.lexeme, format_ast(default)),
+                None => name.lexeme.clone(),
+            }).collect();
+            format!("(function ({:}) {:})", params.join(" "), body.iter().map(|stmt| stmt.to_string()).collect::<Vec<String>>().join("; "))
+        },
+        Expr::Grouping(expr, _) => format!("(group {:})", format_ast(expr)),
+        Expr::Index(expr, _, index, _) => format!("(index {:} {:})", format_ast(expr), format_ast(index)),
+        Expr::List(_, elements, _) => format!("(list{:})", elements.iter().map(|e| format!(" {:}", format_ast(e))).collect::<String>()),
+        Expr::Literal(value, _) => value.lexeme.clone(),
+        Expr::Map(_, entries, _) => format!("(map{:})", entries.iter().map(|(k, v)| format!(" ({:} {:})", format_ast(k), format_ast(v))).collect::<String>()),
+        Expr::Slice(expr, _, start, end, _) => {
+            let start = start.as_ref().map_or("_".to_string(), |e| format_ast(e));
+            let end = end.as_ref().map_or("_".to_string(), |e| format_ast(e));
+            format!("(slice {:} {:} {:})", format_ast(expr), start, end)
+        },
+        Expr::Unary(operator, expr, _) => format!("({:} {:})", operator.lexeme, format_ast(expr)),
     }
 }
 
-pub fn eval_ast(environment: &mut Environment, expr: &Expr, reporter: &mut ErrorReporter) -> Result<EvalResult, Error> {
-    println!("expr = {}", expr);
-
+// Each kind of expression is worked out by a function of its own.  Script calls recurse through here, so keeping
+// this function small keeps the native stack each call takes small too.
+pub fn eval_ast(context: &mut Context, environment: &mut Environment, expr: &Expr, reporter: &mut ErrorReporter) -> Result<EvalResult, Error> {
     match expr {
-        Expr::Binary(left, operator, right) => {
-            if operator.token_type == TokenType::Equal {
-                // println!("Found an equal!");
-                match left.as_ref() {
-                    // TODO: The left-hand side of the assignment will need to get beefed up.
-                    Expr::Literal(left_token) => {
-                        if left_token.token_type == TokenType::Identifier {
-                            let right = eval_ast(environment, right, reporter)?;
-                            // TODO: Maybe write an `eval_var_ref`?
-                            environment.set(&left_token.lexeme, &right);
-                            // println!("Assigning {:} = {:}", left_token.lexeme, right);
-                            return Ok(right);
-                        } else {
-                            return Err(reporter.runtime_error(operator.line, format!("Invalid assignment target: {}", left).as_str()));
-                        }
-                    }
-                    _ => {
-                        return Err(reporter.runtime_error(operator.line, format!("Invalid assignment target: {}", left).as_str()));
-                    }
-                }
+        Expr::AddressOf(_, expr, _) => eval_reference(context, environment, expr, reporter),
+        Expr::Binary(left, operator, right, _) if operator.token_type == TokenType::Equal => eval_assignment(context, environment, left, operator, right, reporter),
+        Expr::Binary(left, operator, right, _) => eval_operation(context, environment, left, operator, right, reporter),
+        Expr::Comparison(first, comparisons, _) => eval_comparison(context, environment, first, comparisons, reporter),
+        Expr::Call(callee, paren, arguments, _) => eval_call(context, environment, callee, paren, arguments, reporter),
+        Expr::Function(_, params, body, _) => eval_function(context, environment, params, body, reporter),
+        Expr::Dot(expr, name, _) => call_method(context, environment, expr, EvalResult::String(name.lexeme.clone()), &[], name.line, reporter),
+        Expr::Grouping(expr, _) => eval_ast(context, environment, expr, reporter),
+        Expr::Index(expr, bracket, index, _) => {
+            let value = eval_ast(context, environment, expr, reporter)?;
+            let index = eval_ast(context, environment, index, reporter)?;
+            eval_index(&value, &index, bracket.line, reporter)
+        },
+        Expr::List(_, elements, _) => {
+            let mut items = Vec::with_capacity(elements.len());
+            for element in elements {
+                items.push(eval_ast(context, environment, element, reporter)?);
             }
+            Ok(EvalResult::new_list(items))
+        },
+        Expr::Map(_, entries, _) => eval_map(context, environment, entries, reporter),
+        Expr::Slice(expr, bracket, start, end, _) => eval_slice_expr(context, environment, expr, bracket, start.as_deref(), end.as_deref(), reporter),
+        Expr::Literal(value, _) => eval_literal(context, environment, value, reporter),
+        Expr::Unary(operator, expr, _) => eval_unary(context, environment, operator, expr, reporter),
+    }
+}
 
-            let left = eval_ast(environment, left, reporter)?;
-            if operator.token_type == TokenType::And {
-                if is_falsy(left) {
-                    return Ok(EvalResult::Number(0.0));
-                } else {
-                    let right = eval_ast(environment, right, reporter)?;
-                    return Ok(EvalResult::Number(if is_truthy(right) { 1.0 } else { 0.0 }));
-                }
+fn eval_assignment(context: &mut Context, environment: &mut Environment, left: &Expr, operator: &Token, right: &Expr, reporter: &mut ErrorReporter) -> Result<EvalResult, Error> {
+    // `@f = ...` is the same as `f = ...`.
+    let left = match left {
+        Expr::AddressOf(_, target, _) => target,
+        _ => left,
+    };
+    match left {
+        // TODO: The left-hand side of the assignment will need to get beefed up.
+        Expr::Literal(left_token, _) if left_token.token_type == TokenType::Identifier => {
+            if Environment::is_scope_name(&left_token.lexeme) {
+                return Err(reporter.runtime_error(operator.line, format!("Can't assign to {}", left_token.lexeme).as_str()));
+            }
+            let right = eval_ast(context, environment, right, reporter)?;
+            // TODO: Maybe write an `eval_var_ref`?
+            environment.set(&left_token.lexeme, &right);
+            // println!("Assigning {:} = {:}", left_token.lexeme, right);
+            Ok(right)
+        }
+        Expr::Index(target, bracket, index, _) => {
+            let target = eval_ast(context, environment, target, reporter)?;
+            let index = eval_ast(context, environment, index, reporter)?;
+            let right = eval_ast(context, environment, right, reporter)?;
+            assign_index(&target, &index, &right, bracket.line, reporter)?;
+            Ok(right)
+        }
+        Expr::Dot(target, name, _) => {
+            let target = eval_ast(context, environment, target, reporter)?;
+            let right = eval_ast(context, environment, right, reporter)?;
+            assign_index(&target, &EvalResult::String(name.lexeme.clone()), &right, name.line, reporter)?;
+            Ok(right)
+        }
+        _ => {
+            Err(reporter.runtime_error(operator.line, format!("Invalid assignment target: {}", left).as_str()))
+        }
+    }
+}
+
+fn eval_operation(context: &mut Context, environment: &mut Environment, left: &Expr, operator: &Token, right: &Expr, reporter: &mut ErrorReporter) -> Result<EvalResult, Error> {
+    let left = eval_ast(context, environment, left, reporter)?;
+    if operator.token_type == TokenType::Isa {
+        let right = eval_ast(context, environment, right, reporter)?;
+        return match is_a(context, &left, &right) {
+            Ok(result) => Ok(EvalResult::Number(if result { 1.0 } else { 0.0 })),
+            Err(e) => Err(reporter.runtime_error(operator.line, &e)),
+        };
+    } else if operator.token_type == TokenType::And {
+        // The right side is only skipped when the left is entirely false.
+        let left = fuzzy_truth(&left);
+        if left == 0.0 {
+            return Ok(EvalResult::Number(0.0));
+        }
+        let right = fuzzy_truth(&eval_ast(context, environment, right, reporter)?);
+        return Ok(EvalResult::Number(left * right));
+    } else if operator.token_type == TokenType::Or {
+        // The right side is only skipped when the left is entirely true.
+        let left = fuzzy_truth(&left);
+        if left == 1.0 {
+            return Ok(EvalResult::Number(1.0));
+        }
+        let right = fuzzy_truth(&eval_ast(context, environment, right, reporter)?);
+        return Ok(EvalResult::Number(left + right - left * right));
+    }
+
+    let right = eval_ast(context, environment, right, reporter)?;
+    eval_binary(left, operator, right, reporter)
+}
+
+// `a < b < c` means `a < b and b < c`, with `b` worked out only once.
+fn eval_comparison(context: &mut Context, environment: &mut Environment, first: &Expr, comparisons: &[(Token, Expr)], reporter: &mut ErrorReporter) -> Result<EvalResult, Error> {
+    let mut left = eval_ast(context, environment, first, reporter)?;
+    let mut result = 1.0;
+    for (operator, right) in comparisons {
+        let right = eval_ast(context, environment, right, reporter)?;
+        result *= fuzzy_truth(&eval_binary(left, operator, right.clone(), reporter)?);
+        left = right;
+    }
+    Ok(EvalResult::Number(result))
+}
+
+fn eval_call(context: &mut Context, environment: &mut Environment, callee: &Expr, paren: &Token, arguments: &[Expr], reporter: &mut ErrorReporter) -> Result<EvalResult, Error> {
+    match callee {
+        Expr::Dot(target, name, _) => return call_method(context, environment, target, EvalResult::String(name.lexeme.clone()), arguments, paren.line, reporter),
+        Expr::Index(target, _, index, _) => {
+            let key = eval_ast(context, environment, index, reporter)?;
+            return call_method(context, environment, target, key, arguments, paren.line, reporter);
+        },
+        _ => {},
+    }
+
+    let callee = eval_reference(context, environment, callee, reporter)?;
+    let mut values = Vec::with_capacity(arguments.len());
+    for argument in arguments {
+        values.push(eval_ast(context, environment, argument, reporter)?);
+    }
+    call_value(context, environment, &callee, values, None, paren.line, reporter)
+}
+
+// Default values are worked out once, when the function is defined.
+fn eval_function(context: &mut Context, environment: &mut Environment, params: &[(Token, Option<Expr>)], body: &Rc<Vec<Stmt>>, reporter: &mut ErrorReporter) -> Result<EvalResult, Error> {
+    let mut function_params = Vec::with_capacity(params.len());
+    for (name, default) in params {
+        let default = match default {
+            Some(default) => eval_ast(context, environment, default, reporter)?,
+            None => EvalResult::Null,
+        };
+        function_params.push(Param { name: name.lexeme.clone(), default });
+    }
+    Ok(EvalResult::Function(Rc::new(Function::new(function_params, body.clone(), Outer::new(&environment.locals, &environment.globals)))))
+}
+
+fn eval_map(context: &mut Context, environment: &mut Environment, entries: &[(Expr, Expr)], reporter: &mut ErrorReporter) -> Result<EvalResult, Error> {
+    let mut map = ValueMap::new();
+    for (key, value) in entries {
+        let key = eval_ast(context, environment, key, reporter)?;
+        let value = eval_ast(context, environment, value, reporter)?;
+        map.set(key, value);
+    }
+    Ok(EvalResult::new_map(map))
+}
 
-            } else if operator.token_type == TokenType::Or {
-                if is_truthy(left) {
-                    return Ok(EvalResult::Number(1.0));
-                } else {
-                    let right = eval_ast(environment, right, reporter)?;
-                    return Ok(EvalResult::Number(if is_truthy(right) { 1.0 } else { 0.0 }));
+fn eval_slice_expr(context: &mut Context, environment: &mut Environment, expr: &Expr, bracket: &Token, start: Option<&Expr>, end: Option<&Expr>, reporter: &mut ErrorReporter) -> Result<EvalResult, Error> {
+    let value = eval_ast(context, environment, expr, reporter)?;
+    let start = match start {
+        Some(start) => Some(eval_ast(context, environment, start, reporter)?),
+        None => None,
+    };
+    let end = match end {
+        Some(end) => Some(eval_ast(context, environment, end, reporter)?),
+        None => None,
+    };
+    eval_slice(&value, start.as_ref(), end.as_ref(), bracket.line, reporter)
+}
+
+fn eval_literal(context: &mut Context, environment: &mut Environment, value: &Token, reporter: &mut ErrorReporter) -> Result<EvalResult, Error> {
+    match value.token_type {
+        // The scanner gives every number its value, but a token built some other way might not have one.
+        TokenType::Number => match value.number {
+            Some(number) => Ok(EvalResult::Number(number)),
+            None => Err(reporter.runtime_error(value.line, format!("Malformed number: {}", value.lexeme).as_str())),
+        },
+        TokenType::String => Ok(EvalResult::String(value.lexeme[1..value.lexeme.len() - 1].replace("\"\"", "\"").to_string())),
+        TokenType::Null => Ok(EvalResult::Null),
+        TokenType::True => Ok(EvalResult::Number(1.0)),
+        TokenType::False => Ok(EvalResult::Number(0.0)),
+        TokenType::Identifier => {
+            let variable = lookup_variable(context, environment, value, reporter)?;
+            call_value(context, environment, &variable, Vec::new(), None, value.line, reporter)
+        },
+        _ => Err(reporter.runtime_error(value.line, "Syntax error.")),
+    }
+}
+
+fn eval_unary(context: &mut Context, environment: &mut Environment, operator: &Token, expr: &Expr, reporter: &mut ErrorReporter) -> Result<EvalResult, Error> {
+    let expr = eval_ast(context, environment, expr, reporter)?;
+    match operator.token_type {
+        TokenType::Minus => match expr {
+            EvalResult::Number(value) => Ok(EvalResult::Number(-value)),
+            _ => Err(reporter.runtime_error(operator.line, format!("Expression type not allowed: {:}", expr).as_str())),
+        },
+        TokenType::Not => Ok(EvalResult::Number(1.0 - fuzzy_truth(&expr))),
+        TokenType::New => match &expr {
+            EvalResult::Map(map) => {
+                if let Some(message) = invalid_new_message(context, map) {
+                    return Err(reporter.runtime_error(operator.line, message));
                 }
+                let mut object = ValueMap::new();
+                object.set(EvalResult::String(ISA_KEY.to_string()), expr.clone());
+                Ok(EvalResult::new_map(object))
+            },
+            _ => Err(reporter.runtime_error(operator.line, "argument to 'new' must be a map")),
+        },
+        _ => Err(reporter.runtime_error(operator.line, format!("Unknown operator: {:}", operator.lexeme).as_str())),
+    }
+}
+
+fn lookup_variable(context: &Context, environment: &Environment, name: &Token, reporter: &mut ErrorReporter) -> Result<EvalResult, Error> {
+    match environment.get(&name.lexeme) {
+        Ok(v) => {
+            Ok(v)
+        },
+        // The built-in type maps and functions, and the prompt's `_`, can be shadowed by variables of the same name.
+        Err(e) => match context.types.get(&name.lexeme).or_else(|| context.intrinsic(&name.lexeme)).or_else(|| context.last_result.clone().filter(|_| name.lexeme == "_")) {
+            Some(v) => Ok(v),
+            None => Err(reporter.runtime_error(name.line, e.as_str())),
+        },
+    }
+}
+
+// Looking up a function by name or with dot syntax calls it.  This evaluates `expr` without making that final call,
+// which is what `@expr` means, and how the function in `expr(arguments)` is found.
+fn eval_reference(context: &mut Context, environment: &mut Environment, expr: &Expr, reporter: &mut ErrorReporter) -> Result<EvalResult, Error> {
+    match expr {
+        Expr::Literal(token, _) if token.token_type == TokenType::Identifier => lookup_variable(context, environment, token, reporter),
+        Expr::Dot(target, name, _) => {
+            let target = eval_ast(context, environment, target, reporter)?;
+            let (value, _) = lookup_member(context, &target, &EvalResult::String(name.lexeme.clone()), name.line, reporter)?;
+            Ok(value)
+        },
+        _ => eval_ast(context, environment, expr, reporter),
+    }
+}
+
+// The most items a list, or characters a string, can be built up to by an operator or intrinsic.
+pub const MAX_LIST_SIZE: usize = 0xFFFFFF;
+pub const MAX_STRING_SIZE: usize = 0xFFFFFF;
+
+// How much native stack nested script calls can use before they're stopped.  A thread spawned by Rust gets 2MB by
+// default.  A call takes several times more stack in a debug build than in a release one, and more again the more
+// blocks it's nested in, so a limit on the number of calls couldn't be both safe and roomy.
+const MAX_STACK_USE: usize = 1536 * 1024;
+
+// The key that links a map to its prototype.
+const ISA_KEY: &str = "__isa";
+
+// How long an `__isa` chain can get before it's assumed to be a loop.
+const MAX_ISA_DEPTH: usize = 256;
+
+// The prototype of `map`, if it has one.
+fn isa_parent(map: &MapRef) -> Option<MapRef> {
+    match map.borrow().get(&EvalResult::String(ISA_KEY.to_string())) {
+        Some(EvalResult::Map(parent)) => Some(parent.clone()),
+        _ => None,
+    }
+}
+
+// Look `key` up in `map`, and failing that, up its `__isa` chain.
+// Also returns the map the key was actually found in, which a method call needs to work out `super`.
+fn lookup_inherited(map: &MapRef, key: &EvalResult) -> Result<Option<(EvalResult, MapRef)>, String> {
+    let mut current = map.clone();
+    for _ in 0..MAX_ISA_DEPTH {
+        if let Some(value) = current.borrow().get(key) {
+            return Ok(Some((value.clone(), current.clone())));
+        }
+        current = match isa_parent(&current) {
+            Some(parent) => parent,
+            None => return Ok(None),
+        };
+    }
+    Err("__isa depth exceeded (perhaps a reference loop?)".to_string())
+}
+
+// `value isa type` holds when `type` is the built-in type of `value`, or appears somewhere in its `__isa` chain.
+// A map is not an instance of itself.
+fn is_a(context: &Context, value: &EvalResult, type_map: &EvalResult) -> Result<bool, String> {
+    let type_map = match type_map {
+        EvalResult::Null => return Ok(matches!(value, EvalResult::Null)),
+        EvalResult::Map(type_map) => type_map,
+        _ => return Ok(false),
+    };
+
+    if context.types.of(value).is_some_and(|builtin| Rc::ptr_eq(builtin, type_map)) {
+        return Ok(true);
+    }
+
+    if let EvalResult::Map(map) = value {
+        let mut current = isa_parent(map);
+        for _ in 0..MAX_ISA_DEPTH {
+            match current {
+                Some(parent) if Rc::ptr_eq(&parent, type_map) => return Ok(true),
+                Some(parent) => current = isa_parent(&parent),
+                None => return Ok(false),
             }
+        }
+        return Err("__isa depth exceeded (perhaps a reference loop?)".to_string());
+    }
 
-            let right = eval_ast(environment, right, reporter)?;
-
-            match (&left, &right) {
-                (EvalResult::Number(l), EvalResult::Number(r)) => match operator.token_type {
-                    TokenType::Plus => Ok(EvalResult::Number(l + r)),
-                    TokenType::Minus => Ok(EvalResult::Number(l - r)),
-                    TokenType::Star => Ok(EvalResult::Number(l * r)),
-                    TokenType::Slash => Ok(EvalResult::Number(l / r)),
-                    
-                    TokenType::Greater => Ok(EvalResult::Number(if l > r { 1.0 } else { 0.0 })),
-                    TokenType::GreaterEqual => Ok(EvalResult::Number(if l >= r { 1.0 } else { 0.0 })),
-                    TokenType::Less => Ok(EvalResult::Number(if l < r { 1.0 } else { 0.0 })),
-                    TokenType::LessEqual => Ok(EvalResult::Number(if l <= r { 1.0 } else { 0.0 })),
-                    TokenType::BangEqual => Ok(EvalResult::Number(if l != r { 1.0 } else { 0.0 })),
-                    TokenType::EqualEqual => Ok(EvalResult::Number(if l == r { 1.0 } else { 0.0 })),
-                    
-                    _ => Err(reporter.runtime_error(operator.line, "Invalid number/number operation.")),
-                },
+    Ok(false)
+}
 
-                (EvalResult::String(l), EvalResult::String(r)) => match operator.token_type {
-                    TokenType::Plus => Ok(EvalResult::String(format!("{}{}", l, r))),
-                    TokenType::Minus => if l.ends_with(r) {  // If `l` ends with `r`, remove `r` from `l`.
-                        Ok(EvalResult::String(l[..l.len() - r.len()].to_string()))
-                    } else {
-                        Ok(left)
-                    }
-                    
-                    TokenType::Greater => Ok(EvalResult::Number(if l > r { 1.0 } else { 0.0 })),
-                    TokenType::GreaterEqual => Ok(EvalResult::Number(if l >= r { 1.0 } else { 0.0 })),
-                    TokenType::Less => Ok(EvalResult::Number(if l < r { 1.0 } else { 0.0 })),
-                    TokenType::LessEqual => Ok(EvalResult::Number(if l <= r { 1.0 } else { 0.0 })),
-                    TokenType::BangEqual => Ok(EvalResult::Number(if l != r { 1.0 } else { 0.0 })),
-                    TokenType::EqualEqual => Ok(EvalResult::Number(if l == r { 1.0 } else { 0.0 })),
-
-                    _ => Err(reporter.runtime_error(operator.line, "Invalid string/string operation.")),
-                },
+// `new` only makes sense for maps, and the built-in type maps other than `map` can't be instantiated either.
+fn invalid_new_message(context: &Context, map: &MapRef) -> Option<&'static str> {
+    let types = &context.types;
+    if Rc::ptr_eq(map, &types.string) {
+        Some("invalid use of 'new'; to create a string, use quotes, e.g. \"foo\"")
+    } else if Rc::ptr_eq(map, &types.list) {
+        Some("invalid use of 'new'; to create a list, use square brackets, e.g. [1,2]")
+    } else if Rc::ptr_eq(map, &types.number) {
+        Some("invalid use of 'new'; to create a number, use a numeric literal, e.g. 42")
+    } else if Rc::ptr_eq(map, &types.function) {
+        Some("invalid use of 'new'; to create a function, use the 'function' keyword")
+    } else {
+        None
+    }
+}
 
-                (EvalResult::String(l), EvalResult::Number(r)) => match operator.token_type {
-                    TokenType::Plus => Ok(EvalResult::String(format!("{}{}", l, r))),
-                    TokenType::Minus => if l.ends_with(&r.to_string()) {  // If `l` ends with `r`, remove `r` from `l`.
-                        Ok(EvalResult::String(l[..l.len() - r.to_string().len()].to_string()))
-                    } else {
-                        Ok(left)
-                    },
-                    TokenType::Star => {
-                        // Repeat 'l' 'r' number of times.
-                        let mut result = String::new();
-                        for _ in 0..r.floor() as usize {
-                            result.push_str(l);
-                        }
-                        Ok(EvalResult::String(result))
-                    },
-                    TokenType::Slash => {
-                        // Calculate the length of `l`.  Divide that length by the ceiling value of `r`.  That number is the length of the substring of `l` to return.
-                        let substring_length = ((l.len() as f64) / r.ceil()) as usize;
-                        Ok(EvalResult::String(l[..substring_length].to_string()))
-                    },
-                    _ => Err(reporter.runtime_error(operator.line, "Invalid string/number operation.")),
-                },
+// Convert a MiniScript index into a position within a sequence of `len` items.
+// Fractional indexes are truncated, and negative indexes count back from the end.
+pub fn resolve_index(index: f64, len: usize) -> Option<usize> {
+    let mut index = index as i64;
+    if index < 0 {
+        index += len as i64;
+    }
+    if index < 0 || index >= len as i64 {
+        None
+    } else {
+        Some(index as usize)
+    }
+}
 
-                (EvalResult::Number(l), EvalResult::String(r)) => match operator.token_type {
-                    TokenType::Plus => Ok(EvalResult::String(format!("{}{}", l, r))),
-                    _ => Err(reporter.runtime_error(operator.line, "Invalid number/string operation.")),
-                },
+// Strings are indexed by code point, not by byte, so that `"héllo"[1]` is "é".
+pub fn char_count(s: &str) -> usize {
+    s.chars().count()
+}
+
+// The byte offset of code point `position` within `s`, or the length of `s` if it doesn't have that many.
+pub fn char_offset(s: &str, position: usize) -> usize {
+    s.char_indices().nth(position).map_or(s.len(), |(offset, _)| offset)
+}
+
+// Slice bounds are clamped to the sequence rather than raising an error.
+fn resolve_slice_bound(bound: Option<&EvalResult>, default: usize, len: usize) -> Result<usize, String> {
+    match bound {
+        None | Some(EvalResult::Null) => Ok(default),
+        Some(EvalResult::Number(bound)) => {
+            let mut bound = *bound as i64;
+            if bound < 0 {
+                bound += len as i64;
+            }
+            Ok(bound.clamp(0, len as i64) as usize)
+        },
+        Some(_) => Err("Slice bounds must be numbers.".to_string()),
+    }
+}
+
+// Apply an operator other than `and`, `or`, `isa` and `=` to values that have already been worked out.
+fn eval_binary(left: EvalResult, operator: &Token, right: EvalResult, reporter: &mut ErrorReporter) -> Result<EvalResult, Error> {
+    // Values of different types are never equal, and can't be ordered.
+    if std::mem::discriminant(&left) != std::mem::discriminant(&right) {
+        match operator.token_type {
+            TokenType::EqualEqual => return Ok(EvalResult::Number(0.0)),
+            TokenType::BangEqual => return Ok(EvalResult::Number(1.0)),
+            TokenType::Greater | TokenType::GreaterEqual | TokenType::Less | TokenType::LessEqual => return Ok(EvalResult::Null),
+            _ => {},
+        }
+    }
+
+    match (&left, &right) {
+        (EvalResult::Number(l), EvalResult::Number(r)) => match operator.token_type {
+            TokenType::Plus => Ok(EvalResult::Number(l + r)),
+            TokenType::Minus => Ok(EvalResult::Number(l - r)),
+            TokenType::Star => Ok(EvalResult::Number(l * r)),
+            TokenType::Slash => Ok(EvalResult::Number(l / r)),
+            // Like C's `fmod`, the result takes the sign of the left operand.
+            TokenType::Percent => Ok(EvalResult::Number(l % r)),
+            TokenType::Caret => Ok(EvalResult::Number(l.powf(*r))),
+            
+            TokenType::Greater => Ok(EvalResult::Number(if l > r { 1.0 } else { 0.0 })),
+            TokenType::GreaterEqual => Ok(EvalResult::Number(if l >= r { 1.0 } else { 0.0 })),
+            TokenType::Less => Ok(EvalResult::Number(if l < r { 1.0 } else { 0.0 })),
+            TokenType::LessEqual => Ok(EvalResult::Number(if l <= r { 1.0 } else { 0.0 })),
+            TokenType::BangEqual => Ok(EvalResult::Number(if l != r { 1.0 } else { 0.0 })),
+            TokenType::EqualEqual => Ok(EvalResult::Number(if l == r { 1.0 } else { 0.0 })),
+            
+            _ => Err(reporter.runtime_error(operator.line, "Invalid number/number operation.")),
+        },
 
-                _ => Err(reporter.runtime_error(operator.line, "Unknown operation type.")),
+        (EvalResult::String(l), EvalResult::String(r)) => match operator.token_type {
+            TokenType::Plus => Ok(EvalResult::String(format!("{}{}", l, r))),
+            TokenType::Minus => if l.ends_with(r) {  // If `l` ends with `r`, remove `r` from `l`.
+                Ok(EvalResult::String(l[..l.len() - r.len()].to_string()))
+            } else {
+                Ok(left)
             }
+            
+            TokenType::Greater => Ok(EvalResult::Number(if l > r { 1.0 } else { 0.0 })),
+            TokenType::GreaterEqual => Ok(EvalResult::Number(if l >= r { 1.0 } else { 0.0 })),
+            TokenType::Less => Ok(EvalResult::Number(if l < r { 1.0 } else { 0.0 })),
+            TokenType::LessEqual => Ok(EvalResult::Number(if l <= r { 1.0 } else { 0.0 })),
+            TokenType::BangEqual => Ok(EvalResult::Number(if l != r { 1.0 } else { 0.0 })),
+            TokenType::EqualEqual => Ok(EvalResult::Number(if l == r { 1.0 } else { 0.0 })),
+
+            _ => Err(reporter.runtime_error(operator.line, "Invalid string/string operation.")),
         },
-        Expr::Grouping(expr) => eval_ast(environment, expr, reporter),
-        Expr::Literal(value) => match value.token_type {
-            TokenType::Number => Ok(EvalResult::Number(value.lexeme.parse().unwrap())),
-            TokenType::String => Ok(EvalResult::String(value.lexeme[1..value.lexeme.len() - 1].replace("\"\"", "\"").to_string())),
-            TokenType::Null => Ok(EvalResult::Null),
-            TokenType::True => Ok(EvalResult::Number(1.0)),
-            TokenType::False => Ok(EvalResult::Number(0.0)),
-            TokenType::Identifier => {
-                match environment.get(&value.lexeme) {
-                    Ok(v) => {
-                        Ok(v.clone())
-                    },
-                    Err(e) => {
-                        Err(reporter.runtime_error(value.line, e.as_str()))
-                    },
+
+        (EvalResult::String(l), EvalResult::Number(r)) => match operator.token_type {
+            TokenType::Plus => Ok(EvalResult::String(format!("{}{}", l, right))),
+            TokenType::Minus => if l.ends_with(&right.to_string()) {  // If `l` ends with `r`, remove `r` from `l`.
+                Ok(EvalResult::String(l[..l.len() - right.to_string().len()].to_string()))
+            } else {
+                Ok(left)
+            },
+            TokenType::Star if !r.is_finite() => Ok(EvalResult::Null),
+            TokenType::Star => {
+                // Repeat 'l' 'r' number of times.
+                let count = r.floor().max(0.0);
+                if char_count(l) as f64 * count > MAX_STRING_SIZE as f64 {
+                    return Err(reporter.runtime_error(operator.line, "string too large"));
                 }
+                Ok(EvalResult::String(l.repeat(count as usize)))
             },
-            _ => Err(reporter.runtime_error(value.line, "Syntax error.")),
+            TokenType::Slash => {
+                // Calculate the length of `l`.  Divide that length by the ceiling value of `r`.  That number is the length of the substring of `l` to return.
+                let substring_length = ((char_count(l) as f64) / r.ceil()) as usize;
+                Ok(EvalResult::String(l[..char_offset(l, substring_length)].to_string()))
+            },
+            _ => Err(reporter.runtime_error(operator.line, "Invalid string/number operation.")),
         },
-        Expr::Unary(operator, expr) => {
-            let expr = eval_ast(environment, expr, reporter)?;
-            match expr {
-                EvalResult::Number(value) => match operator.token_type {
-                    TokenType::Minus => Ok(EvalResult::Number(-value)),
-                    TokenType::Not => Ok(EvalResult::Number(if is_truthy(expr) { 0.0 } else { 1.0 })),
-                    _ => Err(reporter.runtime_error(operator.line, format!("Unknown operator: {:}", operator.lexeme).as_str())),
+
+        (EvalResult::Number(_), EvalResult::String(r)) => match operator.token_type {
+            TokenType::Plus => Ok(EvalResult::String(format!("{}{}", left, r))),
+            _ => Err(reporter.runtime_error(operator.line, "Invalid number/string operation.")),
+        },
+
+        (EvalResult::List(l), EvalResult::List(r)) => match operator.token_type {
+            TokenType::Plus => {
+                let mut items = l.borrow().clone();
+                items.extend(r.borrow().iter().cloned());
+                Ok(EvalResult::new_list(items))
+            },
+            TokenType::BangEqual => Ok(EvalResult::Number(if left != right { 1.0 } else { 0.0 })),
+            TokenType::EqualEqual => Ok(EvalResult::Number(if left == right { 1.0 } else { 0.0 })),
+            _ => Err(reporter.runtime_error(operator.line, "Invalid list/list operation.")),
+        },
+
+        (EvalResult::Map(l), EvalResult::Map(r)) => match operator.token_type {
+            // Adding two maps makes a new map; entries on the right win.
+            TokenType::Plus => {
+                let mut map = l.borrow().clone();
+                for (key, value) in r.borrow().iter() {
+                    map.set(key.clone(), value.clone());
+                }
+                Ok(EvalResult::new_map(map))
+            },
+            TokenType::BangEqual => Ok(EvalResult::Number(if left != right { 1.0 } else { 0.0 })),
+            TokenType::EqualEqual => Ok(EvalResult::Number(if left == right { 1.0 } else { 0.0 })),
+            _ => Err(reporter.runtime_error(operator.line, "Invalid map/map operation.")),
+        },
+
+        (EvalResult::List(l), EvalResult::Number(r)) => match operator.token_type {
+            TokenType::Star if !r.is_finite() => Ok(EvalResult::Null),
+            TokenType::Star => {
+                // The result has `len * r` elements (rounded down), cycling through the original list.
+                let items = l.borrow();
+                let length = (items.len() as f64 * r).max(0.0);
+                if length > MAX_LIST_SIZE as f64 {
+                    return Err(reporter.runtime_error(operator.line, "list too large"));
+                }
+                let length = length as usize;
+                Ok(EvalResult::new_list(items.iter().cycle().take(length).cloned().collect()))
+            },
+            _ => Err(reporter.runtime_error(operator.line, "Invalid list/number operation.")),
+        },
+
+        (EvalResult::String(l), EvalResult::List(_)) if operator.token_type == TokenType::Plus => Ok(EvalResult::String(format!("{}{}", l, right))),
+        (EvalResult::List(_), EvalResult::String(r)) if operator.token_type == TokenType::Plus => Ok(EvalResult::String(format!("{}{}", left, r))),
+
+        // Functions are only equal to themselves.
+        _ => match operator.token_type {
+            TokenType::BangEqual => Ok(EvalResult::Number(if left != right { 1.0 } else { 0.0 })),
+            TokenType::EqualEqual => Ok(EvalResult::Number(if left == right { 1.0 } else { 0.0 })),
+            _ => Err(reporter.runtime_error(operator.line, "Unknown operation type.")),
+        },
+    }
+}
+
+fn eval_index(value: &EvalResult, index: &EvalResult, line: i64, reporter: &mut ErrorReporter) -> Result<EvalResult, Error> {
+    match (value, index) {
+        (EvalResult::List(items), EvalResult::Number(i)) => {
+            let items = items.borrow();
+            match resolve_index(*i, items.len()) {
+                Some(position) => Ok(items[position].clone()),
+                None => Err(reporter.runtime_error(line, format!("Index Error (list index {} out of range)", i).as_str())),
+            }
+        },
+        (EvalResult::String(s), EvalResult::Number(i)) => match resolve_index(*i, char_count(s)).and_then(|position| s.chars().nth(position)) {
+            Some(c) => Ok(EvalResult::String(c.to_string())),
+            None => Err(reporter.runtime_error(line, format!("Index Error (string index {} out of range)", i).as_str())),
+        },
+        (EvalResult::Map(map), _) => match lookup_inherited(map, index) {
+            Ok(Some((value, _))) => Ok(value),
+            Ok(None) => Err(reporter.runtime_error(line, format!("Key Not Found: '{}' not found in map", index).as_str())),
+            Err(e) => Err(reporter.runtime_error(line, &e)),
+        },
+        (EvalResult::List(_), _) => Err(reporter.runtime_error(line, "Index Error (list index must be a number)")),
+        (EvalResult::String(_), _) => Err(reporter.runtime_error(line, "Index Error (string index must be a number)")),
+        (EvalResult::Null, _) => Err(reporter.runtime_error(line, "Null Reference Exception: can't index into null")),
+        _ => Err(reporter.runtime_error(line, format!("Type Error (can't index into {})", value).as_str())),
+    }
+}
+
+fn eval_slice(value: &EvalResult, start: Option<&EvalResult>, end: Option<&EvalResult>, line: i64, reporter: &mut ErrorReporter) -> Result<EvalResult, Error> {
+    slice(value, start, end).map_err(|e| reporter.runtime_error(line, &e))
+}
+
+// The part of a list or string from `start` up to (but not including) `end`, as in `value[start:end]`.
+pub fn slice(value: &EvalResult, start: Option<&EvalResult>, end: Option<&EvalResult>) -> Result<EvalResult, String> {
+    let len = match value {
+        EvalResult::List(items) => items.borrow().len(),
+        EvalResult::String(s) => char_count(s),
+        EvalResult::Null => return Err("Null Reference Exception: can't index into null".to_string()),
+        _ => return Err(format!("Type Error (can't slice {})", value)),
+    };
+
+    let start = resolve_slice_bound(start, 0, len)?;
+    let end = resolve_slice_bound(end, len, len)?.max(start);
+
+    match value {
+        EvalResult::List(items) => Ok(EvalResult::new_list(items.borrow()[start..end].to_vec())),
+        EvalResult::String(s) => Ok(EvalResult::String(s[char_offset(s, start)..char_offset(s, end)].to_string())),
+        _ => unreachable!("Only lists and strings can be sliced."),
+    }
+}
+
+fn assign_index(target: &EvalResult, index: &EvalResult, value: &EvalResult, line: i64, reporter: &mut ErrorReporter) -> Result<(), Error> {
+    match (target, index) {
+        (EvalResult::List(items), EvalResult::Number(i)) => {
+            let mut items = items.borrow_mut();
+            match resolve_index(*i, items.len()) {
+                Some(position) => {
+                    items[position] = value.clone();
+                    Ok(())
                 },
-                _ => Err(reporter.runtime_error(operator.line, format!("Expression type not allowed: {:}", expr).as_str())),
+                None => Err(reporter.runtime_error(line, format!("Index Error (list index {} out of range)", i).as_str())),
             }
         },
+        (EvalResult::Map(map), _) => {
+            value_map::set_entry(map, index.clone(), value.clone());
+            Ok(())
+        },
+        (EvalResult::List(_), _) => Err(reporter.runtime_error(line, "Index Error (list index must be a number)")),
+        (EvalResult::Null, _) => Err(reporter.runtime_error(line, "Null Reference Exception: can't set an index in null")),
+        _ => Err(reporter.runtime_error(line, format!("Type Error (can't set an index in {})", target).as_str())),
+    }
+}
+
+// The values a method call binds to `self` and `super` in the called function's locals.
+struct Receiver {
+    self_value: EvalResult,
+    super_value: EvalResult,
+}
+
+// Call `target.key(arguments)` or `target[key](arguments)`.
+// A function found on a map is called as a method: `self` is the map, and `super` is the prototype of the map the
+// function was actually found in.  A call through `super` keeps the current `self`.
+fn call_method(context: &mut Context, environment: &mut Environment, target: &Expr, key: EvalResult, arguments: &[Expr], line: i64, reporter: &mut ErrorReporter) -> Result<EvalResult, Error> {
+    let object = eval_ast(context, environment, target, reporter)?;
+    let mut values = Vec::with_capacity(arguments.len());
+    for argument in arguments {
+        values.push(eval_ast(context, environment, argument, reporter)?);
+    }
+
+    let (callee, found_in) = lookup_member(context, &object, &key, line, reporter)?;
+    let found_in = match found_in {
+        Some(found_in) => found_in,
+        None => return call_value(context, environment, &callee, values, None, line, reporter),
+    };
+
+    let is_super = matches!(target, Expr::Literal(token, _) if token.token_type == TokenType::Identifier && token.lexeme == "super");
+    let receiver = Receiver {
+        self_value: if is_super { environment.get("self").unwrap_or(EvalResult::Null) } else { object.clone() },
+        super_value: isa_parent(&found_in).map_or(EvalResult::Null, EvalResult::Map),
+    };
+    call_value(context, environment, &callee, values, Some(receiver), line, reporter)
+}
+
+// Look up `object.key`, along with the map it was found in.  A map's own entries and prototypes come first, and then
+// the type map for the kind of value `object` is, so `"abc".len` finds `string.len`.  Indexes into lists and strings
+// aren't found in any map.
+fn lookup_member(context: &Context, object: &EvalResult, key: &EvalResult, line: i64, reporter: &mut ErrorReporter) -> Result<(EvalResult, Option<MapRef>), Error> {
+    if let EvalResult::Map(map) = object {
+        match lookup_inherited(map, key) {
+            Ok(Some((value, found_in))) => return Ok((value, Some(found_in))),
+            Ok(None) => {},
+            Err(e) => return Err(reporter.runtime_error(line, &e)),
+        }
+    }
+
+    if !matches!(key, EvalResult::Number(_)) {
+        if let Some(type_map) = context.types.of(object) {
+            match lookup_inherited(type_map, key) {
+                Ok(Some((value, found_in))) => return Ok((value, Some(found_in))),
+                Ok(None) => {},
+                Err(e) => return Err(reporter.runtime_error(line, &e)),
+            }
+        }
+    }
+
+    Ok((eval_index(object, key, line, reporter)?, None))
+}
+
+// Calling something that isn't a function just gives back its value, as long as there are no arguments.
+fn call_value(context: &mut Context, environment: &mut Environment, callee: &EvalResult, arguments: Vec<EvalResult>, receiver: Option<Receiver>, line: i64, reporter: &mut ErrorReporter) -> Result<EvalResult, Error> {
+    match callee {
+        EvalResult::Function(function) => call_function(context, environment, function, arguments, receiver, line, reporter),
+        _ if arguments.is_empty() => Ok(callee.clone()),
+        _ => Err(reporter.runtime_error(line, "Too Many Arguments")),
     }
 }
 
-pub fn eval_stmts(environment: &mut Environment, stmts: &Vec<Stmt>, reporter: &mut ErrorReporter) -> Result<EvalResult, Error> {
+// Each call gets a fresh set of locals holding its arguments.  Missing arguments take the parameter's default.
+// The body can also see the scope the function was defined in, and the globals, but not the caller's locals.
+fn call_function(context: &mut Context, environment: &mut Environment, function: &Function, mut arguments: Vec<EvalResult>, receiver: Option<Receiver>, line: i64, reporter: &mut ErrorReporter) -> Result<EvalResult, Error> {
+    // A method whose first parameter is `self` gets the value it was called on there, so `s.len` is `len(s)`.
+    let self_param = function.params.first().is_some_and(|param| param.name == "self");
+    if let (true, Some(receiver)) = (self_param, &receiver) {
+        arguments.insert(0, receiver.self_value.clone());
+    }
+
+    if arguments.len() > function.params.len() {
+        return Err(reporter.runtime_error(line, "Too Many Arguments"));
+    }
+
+    let mut arguments = arguments.into_iter();
+    let arguments: Vec<EvalResult> = function.params.iter()
+        .map(|param| arguments.next().unwrap_or_else(|| param.default.clone()))
+        .collect();
+
+    let (body, outer) = match &function.body {
+        Body::Script(body, outer) => (body, outer),
+        Body::Native(native) => return native(context, arguments).map_err(|e| reporter.runtime_error(line, &e)),
+    };
+
+    let mut locals = Environment::new_call(outer.scope(), environment.globals.clone());
+    for (param, value) in function.params.iter().zip(arguments) {
+        locals.set(&param.name, &value);
+    }
+    if let Some(receiver) = receiver {
+        if !self_param {
+            locals.set("self", &receiver.self_value);
+        }
+        locals.set("super", &receiver.super_value);
+    }
+
+    // Runaway recursion is stopped with an error, rather than overflowing the stack and aborting the host.
+    let position = stack_position();
+    if context.call_depth == 0 {
+        context.stack_base = position;
+    } else if context.stack_base.abs_diff(position) > MAX_STACK_USE {
+        return Err(reporter.runtime_error(line, "Stack Overflow"));
+    }
+    context.call_depth += 1;
+    let flow = exec_stmts(context, &mut locals, body, reporter);
+    context.call_depth -= 1;
+
+    match flow? {
+        Flow::Return(value) => Ok(value),
+        _ => Ok(EvalResult::Null),
+    }
+}
+
+// The item a `for` loop sees on its `index`th pass, or `None` once the sequence runs out.
+// Lists and maps are looked up afresh on every pass, so the loop sees any changes the body makes to them.
+// Map entries are handed out as little `{"key": k, "value": v}` maps.
+fn loop_item(sequence: &EvalResult, index: usize) -> Option<EvalResult> {
+    match sequence {
+        EvalResult::List(items) => items.borrow().get(index).cloned(),
+        EvalResult::Map(map) => map.borrow().entry_at(index).map(|(key, value)| {
+            let mut entry = ValueMap::new();
+            entry.set(EvalResult::String("key".to_string()), key.clone());
+            entry.set(EvalResult::String("value".to_string()), value.clone());
+            EvalResult::new_map(entry)
+        }),
+        _ => None,
+    }
+}
+
+// Roughly where the top of the native stack is.
+fn stack_position() -> usize {
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
+
+// How control left a block of statements.
+enum Flow {
+    // Ran off the end of the block, with the value of the last statement.
+    Normal(EvalResult),
+    Break,
+    Continue,
+    Return(EvalResult),
+}
+
+pub fn eval_stmts(context: &mut Context, environment: &mut Environment, stmts: &Vec<Stmt>, reporter: &mut ErrorReporter) -> Result<EvalResult, Error> {
+    match exec_stmts(context, environment, stmts, reporter)? {
+        Flow::Normal(result) => Ok(result),
+        // The parser doesn't allow `break` or `continue` outside of a loop.
+        Flow::Break | Flow::Continue => Ok(EvalResult::Null),
+        // A `return` outside of a function just ends the program.
+        Flow::Return(_) => Ok(EvalResult::Null),
+    }
+}
+
+// Each kind of statement is run by a function of its own, for the same reason as in `eval_ast`.
+fn exec_stmts(context: &mut Context, environment: &mut Environment, stmts: &Vec<Stmt>, reporter: &mut ErrorReporter) -> Result<Flow, Error> {
     let mut result = EvalResult::Null;
     for stmt in stmts {
         // println!("stmt={}", stmt);
-        match stmt {
-            Stmt::Expression(expr) => {
-                result = eval_ast(environment, expr, reporter)?;
-                environment.set("_", &result);
-            },
-            Stmt::Print(expr) => {
-                println!("PRINT: {:?}", expr);
-                println!("{:}", eval_ast(environment, expr, reporter)?);
-                result = EvalResult::Null;
-            },
+        let flow = match stmt {
+            Stmt::Expression(expr, _) => exec_expression(context, environment, expr, reporter)?,
+            Stmt::If(condition, then_branch, else_branch, _) => exec_if(context, environment, condition, then_branch, else_branch, reporter)?,
+            Stmt::While(condition, body, _) => exec_while(context, environment, condition, body, reporter)?,
+            Stmt::For(variable, sequence, body, _) => exec_for(context, environment, variable, sequence, body, reporter)?,
+            Stmt::Break(_) => Flow::Break,
+            Stmt::Continue(_) => Flow::Continue,
+            Stmt::Return(value, _) => exec_return(context, environment, value.as_ref(), reporter)?,
             // Stmt::Assignment(name, value) => {
-            //     let value = eval_ast(environment, value, reporter)?;
+            //     let value = eval_ast(context, environment, value, reporter)?;
             //     environment.set(&name, &value);
             // }
             // _ => {
             //     return Err(reporter.runtime_error(stmt.line(), "Syntax error."))
             // },
+        };
+        match flow {
+            Flow::Normal(value) => result = value,
+            flow => return Ok(flow),
         }
     }
     
     // The final result will be returned.
-    Ok(result)
+    Ok(Flow::Normal(result))
+}
+
+fn exec_expression(context: &mut Context, environment: &mut Environment, expr: &Expr, reporter: &mut ErrorReporter) -> Result<Flow, Error> {
+    let result = eval_ast(context, environment, expr, reporter)?;
+    // An assignment is a statement of its own, so it has no value to show at the prompt.
+    if matches!(expr, Expr::Binary(_, operator, _, _) if operator.token_type == TokenType::Equal) {
+        return Ok(Flow::Normal(EvalResult::Null));
+    }
+    Ok(Flow::Normal(result))
+}
+
+fn exec_if(context: &mut Context, environment: &mut Environment, condition: &Expr, then_branch: &Vec<Stmt>, else_branch: &Vec<Stmt>, reporter: &mut ErrorReporter) -> Result<Flow, Error> {
+    let branch = if is_truthy(eval_ast(context, environment, condition, reporter)?) { then_branch } else { else_branch };
+    match exec_stmts(context, environment, branch, reporter)? {
+        Flow::Normal(_) => Ok(Flow::Normal(EvalResult::Null)),
+        flow => Ok(flow),
+    }
+}
+
+fn exec_while(context: &mut Context, environment: &mut Environment, condition: &Expr, body: &Vec<Stmt>, reporter: &mut ErrorReporter) -> Result<Flow, Error> {
+    while is_truthy(eval_ast(context, environment, condition, reporter)?) {
+        match exec_stmts(context, environment, body, reporter)? {
+            Flow::Break => break,
+            flow @ Flow::Return(_) => return Ok(flow),
+            Flow::Normal(_) | Flow::Continue => {},
+        }
+    }
+    Ok(Flow::Normal(EvalResult::Null))
+}
+
+fn exec_for(context: &mut Context, environment: &mut Environment, variable: &Token, sequence: &Expr, body: &Vec<Stmt>, reporter: &mut ErrorReporter) -> Result<Flow, Error> {
+    let sequence = match eval_ast(context, environment, sequence, reporter)? {
+        // A string can't change, so its characters are split out once instead of being counted off on every pass.
+        EvalResult::String(s) => EvalResult::new_list(s.chars().map(|c| EvalResult::String(c.to_string())).collect()),
+        sequence @ (EvalResult::List(_) | EvalResult::Map(_)) => sequence,
+        EvalResult::Null => return Err(reporter.runtime_error(variable.line, "Null Reference Exception: can't iterate over null")),
+        sequence => return Err(reporter.runtime_error(variable.line, format!("Type Error (can't iterate over {})", sequence).as_str())),
+    };
+
+    let mut index = 0;
+    while let Some(item) = loop_item(&sequence, index) {
+        environment.set(&variable.lexeme, &item);
+        match exec_stmts(context, environment, body, reporter)? {
+            Flow::Break => break,
+            flow @ Flow::Return(_) => return Ok(flow),
+            Flow::Normal(_) | Flow::Continue => {},
+        }
+        index += 1;
+    }
+    Ok(Flow::Normal(EvalResult::Null))
+}
+
+fn exec_return(context: &mut Context, environment: &mut Environment, value: Option<&Expr>, reporter: &mut ErrorReporter) -> Result<Flow, Error> {
+    let value = match value {
+        Some(value) => eval_ast(context, environment, value, reporter)?,
+        None => EvalResult::Null,
+    };
+    Ok(Flow::Return(value))
 }
 
 impl Display for Expr {
@@ -230,19 +923,23 @@ impl Debug for Expr {
 
 #[cfg(test)]
 mod tests {
-    use crate::{environment::Environment, error_reporter::ErrorReporter, expression::eval_ast, parser::Parser, scanner::Scanner, statement::Stmt, EvalResult, Expr, Token, TokenType};
+    use crate::{context::Context, environment::Environment, error_reporter::ErrorReporter, expression::{eval_ast, eval_stmts}, parser::Parser, scanner::Scanner, statement::Stmt, EvalResult, Expr, Span, Token, TokenType};
 
     #[test]
     fn test_print_ast() {
+        let span = Span::default();
         let expr = Expr::Binary(
             Box::new(Expr::Unary(
                 Token::new(TokenType::Minus, "-", 1),
-                Box::new(Expr::Literal(Token::new(TokenType::Number, "123", 1)))
+                Box::new(Expr::Literal(Token::new(TokenType::Number, "123", 1), span)),
+                span
             )),
             Token::new(TokenType::Star, "*", 1),
             Box::new(Expr::Grouping(
-                Box::new(Expr::Literal(Token::new(TokenType::Number, "45.67", 1)))
-            ))
+                Box::new(Expr::Literal(Token::new(TokenType::Number, "45.67", 1), span)),
+                span
+            )),
+            span
         );
 
         // let result = format_ast(&expr);
@@ -269,12 +966,39 @@ mod tests {
         test_eval("1 or 0", EvalResult::Number(1.0));
         test_eval("-1", EvalResult::Number(-1.0));
         test_eval("not 1", EvalResult::Number(0.0));
+        test_eval("0.6 and 0.5", EvalResult::Number(0.3));
+        test_eval("0.5 or 0.5", EvalResult::Number(0.75));
+        test_eval("not 0.25", EvalResult::Number(0.75));
+        test_eval("-1 or 0", EvalResult::Number(1.0));
+        test_eval("5 and 10", EvalResult::Number(1.0));
+        test_eval("1 and null", EvalResult::Number(0.0));
+        test_eval("0.5 and \"abc\"", EvalResult::Number(0.5));
+        test_eval("not []", EvalResult::Number(1.0));
+        test_eval("1 or 0 and 0", EvalResult::Number(1.0));
+        test_eval("not 2 > 3", EvalResult::Number(1.0));
+        test_eval("1 < 2 < 3", EvalResult::Number(1.0));
+        test_eval("3 > 2 > 1", EvalResult::Number(1.0));
+        test_eval("1 == 0 < 8 < 10", EvalResult::Number(0.0));
+        test_eval("1 != 1 < 1", EvalResult::Number(0.0));
+        test_eval("1 < 5 <= 5 != 4", EvalResult::Number(1.0));
         test_eval("true", EvalResult::Number(1.0));
         test_eval("false", EvalResult::Number(0.0));
         test_eval("1+2*3", EvalResult::Number(7.0));
+        test_eval("1e6", EvalResult::Number(1e6));
+        test_eval("2.5E-3", EvalResult::Number(0.0025));
+        test_eval("-1.23e+9", EvalResult::Number(-1.23e9));
+        test_eval(".5e1", EvalResult::Number(5.0));
         test_eval("(1+2)*3", EvalResult::Number(9.0));
         test_eval("1+2*3+4/5", EvalResult::Number(7.8));
         test_eval("1+2*3+4/5*6", EvalResult::Number(11.8));
+        test_eval("-2^3", EvalResult::Number(-8.0));
+        test_eval("2^3^2", EvalResult::Number(512.0));
+        test_eval("2^-1", EvalResult::Number(0.5));
+        test_eval("7 % 3", EvalResult::Number(1.0));
+        test_eval("-1 % 3", EvalResult::Number(-1.0));
+        test_eval("1 % -3", EvalResult::Number(1.0));
+        test_eval("5.5 % 2", EvalResult::Number(1.5));
+        test_eval("2 * 7 % 4", EvalResult::Number(2.0));
         test_eval("\"Hello!\"", EvalResult::String("Hello!".to_string()));
         test_eval("\"Hello\"\"World\"", EvalResult::String("Hello\"World".to_string()));
         test_eval("\"Hello\" + \" \" + \"World\"", EvalResult::String("Hello World".to_string()));
@@ -282,6 +1006,16 @@ mod tests {
         test_eval("\"abcdefg\" - \"efg\"", EvalResult::String("abcd".to_string()));
         test_eval("\"12345\" + 6", EvalResult::String("123456".to_string()));
         test_eval("6 + \"12345\"", EvalResult::String("612345".to_string()));
+        test_eval("\"\" + 1/3", EvalResult::String("0.333333".to_string()));
+        test_eval("\"\" + 2.5", EvalResult::String("2.5".to_string()));
+        test_eval("\"\" + 1.23e9", EvalResult::String("1230000000".to_string()));
+        test_eval("\"\" + -1.23e-9", EvalResult::String("-1.230000E-09".to_string()));
+        test_eval("[-0, -6 % 3, 0 * -1] + \"\"", EvalResult::String("[0, 0, 0]".to_string()));
+        test_eval("\"\" + 12345678901.5", EvalResult::String("1.234568E+10".to_string()));
+        test_eval("\"\" + 0/0", EvalResult::String("NaN".to_string()));
+        test_eval("\"\" + -1/0", EvalResult::String("-Infinity".to_string()));
+        test_eval("[1/4, \"x\"] + \"\"", EvalResult::String("[0.25, \"x\"]".to_string()));
+        test_eval("\"abc10\" - 10", EvalResult::String("abc".to_string()));
         test_eval("\"12345\" - 6", EvalResult::String("12345".to_string()));
         test_eval("\"12345\" - 45", EvalResult::String("123".to_string()));
         test_eval("\"12345678\" / 2", EvalResult::String("1234".to_string()));
@@ -290,13 +1024,115 @@ mod tests {
         test_eval("\"12345678\" / 5", EvalResult::String("1".to_string()));
         test_eval("\"123\" * 3", EvalResult::String("123123123".to_string()));
 
+        test_eval("[1, 2, 3]", list(&[1.0, 2.0, 3.0]));
+        test_eval("[1, 2, 3][1]", EvalResult::Number(2.0));
+        test_eval("[1, 2, 3][-1]", EvalResult::Number(3.0));
+        test_eval("[1, 2, 3][1.7]", EvalResult::Number(2.0));
+        test_eval("[0, 10, 20, 30, 40][1:-1]", list(&[10.0, 20.0, 30.0]));
+        test_eval("[0, 10, 20, 30, 40][:2]", list(&[0.0, 10.0]));
+        test_eval("[0, 10, 20, 30, 40][3:]", list(&[30.0, 40.0]));
+        test_eval("[1, 2] + [3]", list(&[1.0, 2.0, 3.0]));
+        test_eval("[1, 2, 3] * 2.5", list(&[1.0, 2.0, 3.0, 1.0, 2.0, 3.0, 1.0]));
+        test_eval("[1, 2, 3] * -1", list(&[]));
+        test_eval("[1, [2, 3]] == [1, [2, 3]]", EvalResult::Number(1.0));
+        test_eval("[1, 2] == [2, 1]", EvalResult::Number(0.0));
+        test_eval("\"abcde\"[-2:]", EvalResult::String("de".to_string()));
+        test_eval("\"abcde\"[1]", EvalResult::String("b".to_string()));
+        test_eval("\"héllo wörld\"[-4]", EvalResult::String("ö".to_string()));
+        test_eval("\"日本語です\"[1:3]", EvalResult::String("本語".to_string()));
+        test_eval("\"añbc\" / 2", EvalResult::String("añ".to_string()));
+
+        test_eval("{1: 2, \"a\": 3}[\"a\"]", EvalResult::Number(3.0));
+        test_eval("{[1, 2]: 3}[[1] + [2]]", EvalResult::Number(3.0));
+        test_eval("{null: 1}[null]", EvalResult::Number(1.0));
+        test_eval("{1: 2} + {1: 3, 2: 4} == {2: 4, 1: 3}", EvalResult::Number(1.0));
+        test_eval("{1: 2} == {1: 3}", EvalResult::Number(0.0));
+
         // Not sure if these are required cases.  They're weird.
         // test_eval("\"123\" * 3.1", EvalResult::String("123123123".to_string()));
         // test_eval("\"123\" * 3.6", EvalResult::String("1231231231".to_string()));
         // test_eval("\"123\" * 3.7", EvalResult::String("12312312312".to_string()));
     }
 
+    #[test]
+    fn test_number_without_value() {
+        let expr = Expr::Literal(Token::new(TokenType::Number, "12", 3), Span::default());
+        let result = eval_ast(&mut Context::new(), &mut Environment::new_root(), &expr, &mut ErrorReporter::new());
+        assert_eq!(result.map_err(|e| e.to_string()), Err("Runtime Error: Malformed number: 12 [line 3]".to_string()));
+    }
+
+    #[test]
+    fn test_eval_stmts() {
+        test_run("f = function(a, b=2)\n globals.x = a * b\nend function\nf 21\nm = {}\nm.f = @f\nm.f 5, 3", "x", EvalResult::Number(15.0));
+        test_run("f = function(a)\n globals.x = a\nend function\nf -1", "x", EvalResult::Number(-1.0));
+        test_run("x = 0\nif 0 then\n x = 1\nelse if 1 then\n x = 2\nelse\n x = 3\nend if", "x", EvalResult::Number(2.0));
+        test_run("i = 0; n = 0\nwhile i < 10\n i = i + 1\n if i == 3 then continue\n if i > 5 then break\n n = n + i\nend while", "n", EvalResult::Number(12.0));
+        test_run("i = 0\nwhile 1\n while 1\n  break\n end while\n i = i + 1\n if i == 4 then break\nend while", "i", EvalResult::Number(4.0));
+        test_run("n = 0\nfor x in [1, 2, 3, 4]\n if x == 2 then continue\n n = n + x\nend for", "n", EvalResult::Number(8.0));
+        test_run("s = \"\"\nfor c in \"abc\"\n s = c + s\nend for", "s", EvalResult::String("cba".to_string()));
+        test_run("s = \"añ日\"\nfor c in s\n s = s + c\nend for", "s", EvalResult::String("añ日añ日".to_string()));
+        test_run("s = \"\"\nfor kv in {\"a\": 1, \"b\": 2}\n s = s + kv.key + kv.value\nend for", "s", EvalResult::String("a1b2".to_string()));
+        test_run("f = function(a, b=2)\n return a * 10 + b\nend function\nx = f(1) + f(3, 4)", "x", EvalResult::Number(46.0));
+        test_run("f = function(n)\n if n <= 1 then return 1\n return n * f(n - 1)\nend function\nx = f(5)", "x", EvalResult::Number(120.0));
+        test_run("f = function\n for i in [1, 2, 3]\n  while 1\n   return i\n  end while\n end for\nend function\nx = f()", "x", EvalResult::Number(1.0));
+        test_run("f = function(a)\n return\nend function\nx = f(1)", "x", EvalResult::Null);
+
+        // Only the locals, the defining scope and the globals are searched; never the caller's locals.
+        test_run("a = 1\nf = function\n a = 2\n return g()\nend function\ng = function\n return a\nend function\nx = f()", "x", EvalResult::Number(1.0));
+        test_run("f = function(a)\n g = function\n  return a\n end function\n return g\nend function\nh = f(5)\nx = h()", "x", EvalResult::Number(5.0));
+        test_run("a = 1\nf = function\n a = 2\nend function\nf()\nx = a", "x", EvalResult::Number(1.0));
+
+        // The scopes are live maps.
+        test_run("a = 1\nf = function\n globals.a = 2\n locals[\"b\"] = 3\n return b\nend function\nx = f() + a", "x", EvalResult::Number(5.0));
+        test_run("f = function(a)\n g = function\n  outer.a = outer.a + 1\n end function\n g()\n return a\nend function\nx = f(1)", "x", EvalResult::Number(2.0));
+        test_run("a = 1\nx = outer == globals and locals == globals", "x", EvalResult::Number(1.0));
+
+        // Values of different types are never equal, and comparing their order gives null.
+        test_run("x = [1 == \"1\", 1 != \"1\", [1] == 1, {} != [], null == 0, \"a\" == null]", "x", list(&[0.0, 1.0, 0.0, 1.0, 0.0, 0.0]));
+        test_run("x = [1 < \"2\", [1] > 0, null <= 1, \"b\" >= 2]", "x", EvalResult::new_list(vec![EvalResult::Null; 4]));
+
+        // A map can hold itself, even as a key.
+        test_run("m = {}\nm[m] = 1\nm[{}] = 2\nm[[m]] = 3\nm.self = m\nx = m.len", "x", EvalResult::Number(4.0));
+
+        // Prototype-based objects.
+        test_run("A = {\"one\": 1}\nb = new A\nb.two = 2\nx = [b.one, b.two, A == {\"one\": 1}]", "x", list(&[1.0, 2.0, 1.0]));
+        test_run("A = {}\nB = new A\nb = new B\nx = [b isa A, b isa B, A isa A, b isa map, 1 isa number, null isa null, b isa null]", "x", list(&[1.0, 1.0, 0.0, 1.0, 1.0, 1.0, 0.0]));
+        test_run("A = {\"n\": 1}\nA.get = function(d)\n return self.n + d\nend function\nb = new A\nb.n = 5\nx = b.get(2) + b[\"get\"](3)", "x", EvalResult::Number(15.0));
+        test_run("A = {\"n\": 1}\nA.add = function(self, v)\n return self.n + v\nend function\nb = new A\nb.n = 4\nf = @A.add\nx = [b.add(5), f(b, 2)]", "x", list(&[9.0, 6.0]));
+        test_run("A = {\"n\": 0}\nA.next = function\n self.n = self.n + 1\n return self.n\nend function\nc = new A\nc.next\nx = c.next + c.next", "x", EvalResult::Number(5.0));
+
+        // Looking up a function calls it, unless the lookup is preceded by `@`.
+        test_run("f = function(a=1)\n return a * 2\nend function\nx = [f, f(3), @f == @f, @f isa funcRef]", "x", list(&[2.0, 6.0, 1.0, 1.0]));
+        test_run("m = {}\nm.f = function\n return 42\nend function\ng = @m.f\nd = {}\nd[@g] = 1\nx = [g, d[@m.f], m[\"f\"] == @g]", "x", list(&[42.0, 1.0, 1.0]));
+        test_run("f = function\n return 1\nend function\n@g = @f\nx = g", "x", EvalResult::Number(1.0));
+        test_run("A = {}\nA.f = function\n return \"A\" + self.name\nend function\nB = new A\nB.f = function\n return super.f() + \"B\"\nend function\nc = new B\nc.name = \"c\"\nx = c.f()", "x", EvalResult::String("AcB".to_string()));
+    }
+
+    // Run a whole program, then check the value left in a global variable.
+    fn test_run(input: &str, name: &str, expected: EvalResult) {
+        let mut context = Context::new();
+        let mut environment = Environment::new_root();
+        let mut reporter = ErrorReporter::new();
+
+        let mut scanner = Scanner::new(input);
+        scanner.scan_tokens(&mut reporter);
+
+        let mut parser = Parser::new(scanner.tokens);
+        let stmts = parser.parse(&mut reporter).unwrap();
+        assert!(!reporter.had_error(), "Syntax error.");
+
+        if let Err(err) = eval_stmts(&mut context, &mut environment, &stmts, &mut reporter) {
+            panic!("{}", err);
+        }
+        assert_eq!(environment.get(name), Ok(expected));
+    }
+
+    fn list(values: &[f64]) -> EvalResult {
+        EvalResult::new_list(values.iter().map(|v| EvalResult::Number(*v)).collect())
+    }
+
     fn test_eval(input: &str, expected: EvalResult) {
+        let mut context = Context::new();
         let mut environment = Environment::new_root();
         let mut reporter = ErrorReporter::new();
 
@@ -321,8 +1157,8 @@ mod tests {
         // println!("{:}", expr);
         
         match &stmts[0] {
-            Stmt::Expression(expr) => {
-                match eval_ast(&mut environment, &expr, &mut reporter) {
+            Stmt::Expression(expr, _) => {
+                match eval_ast(&mut context, &mut environment, expr, &mut reporter) {
                     Ok(result) => assert_eq!(result, expected),
                     Err(err) => panic!("{}", err),
                 }
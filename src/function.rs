@@ -0,0 +1,89 @@
+// A function value: its parameters, and either the statements that make up its body along with the scope it was
+// defined in, or the Rust code behind a built-in function.
+// A script's body is shared with the AST it was parsed from, so creating a function value is cheap.
+
+use std::{cell::RefCell, fmt::{Debug, Display, Formatter}, rc::{Rc, Weak}};
+
+use crate::{context::Context, environment::Scope, statement::Stmt, EvalResult, ValueMap};
+
+// The Rust side of a built-in function.  It gets one argument per parameter, with defaults filled in, and returns
+// the result or the message of a runtime error.
+pub type NativeFn = Rc<dyn Fn(&mut Context, Vec<EvalResult>) -> Result<EvalResult, String>>;
+
+#[derive(Debug, Clone)]
+pub struct Param {
+    pub name: String,
+    // The value used when a call doesn't supply this argument.  Parameters without a default get `null`.
+    pub default: EvalResult,
+}
+
+impl Param {
+    pub fn new(name: &str, default: EvalResult) -> Self {
+        Self { name: name.to_string(), default }
+    }
+}
+
+pub enum Body {
+    // The statements of a script function, and the locals of the code that created it, which it sees as `outer`.
+    Script(Rc<Vec<Stmt>>, Outer),
+    Native(NativeFn),
+}
+
+// The scope a script function was created in.
+pub enum Outer {
+    // The locals of the call that created the function.  They're kept alive for as long as the function is.
+    Locals(Scope),
+    // The globals, for a function created at the top level.  Such a function is usually stored in the globals as well,
+    // so holding them strongly would make a cycle that keeps both alive after the interpreter is dropped.
+    Globals(Weak<RefCell<ValueMap>>),
+}
+
+impl Outer {
+    pub fn new(locals: &Scope, globals: &Scope) -> Self {
+        if Rc::ptr_eq(locals, globals) {
+            Outer::Globals(Rc::downgrade(globals))
+        } else {
+            Outer::Locals(locals.clone())
+        }
+    }
+
+    // Globals that are gone have nothing left in them, so an empty scope stands in for them.
+    pub fn scope(&self) -> Scope {
+        match self {
+            Outer::Locals(scope) => scope.clone(),
+            Outer::Globals(globals) => globals.upgrade().unwrap_or_default(),
+        }
+    }
+}
+
+pub struct Function {
+    pub params: Vec<Param>,
+    pub body: Body,
+}
+
+impl Function {
+    pub fn new(params: Vec<Param>, body: Rc<Vec<Stmt>>, outer: Outer) -> Self {
+        Self { params, body: Body::Script(body, outer) }
+    }
+
+    pub fn native(params: Vec<Param>, body: impl Fn(&mut Context, Vec<EvalResult>) -> Result<EvalResult, String> + 'static) -> Self {
+        Self { params, body: Body::Native(Rc::new(body)) }
+    }
+}
+
+impl Display for Function {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let params: Vec<String> = self.params.iter().map(|param| match param.default {
+            EvalResult::Null => param.name.clone(),
+            _ => format!("{}={}", param.name, param.default.code_form()),
+        }).collect();
+        write!(f, "FUNCTION({})", params.join(", "))
+    }
+}
+
+// The outer scope usually holds the function itself, so it's left out here.
+impl Debug for Function {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
@@ -0,0 +1,607 @@
+// The built-in functions that every script can call without defining them.
+
+use std::{cell::Cell, cmp::Ordering, f64::consts::{E, PI}, rc::Rc, time::{SystemTime, UNIX_EPOCH}};
+
+use crate::{context::Context, expression::{char_count, char_offset, is_truthy, resolve_index, slice, MAX_LIST_SIZE}, function::{Function, Param}, value_map::{self, MapRef}, EvalResult};
+
+pub fn add_standard(context: &mut Context) {
+    // `delim` is printed after `s`; an empty one lets the next `print` carry on the same line.
+    let print_params = vec![Param::new("s", EvalResult::String(String::new())), Param::new("delim", EvalResult::String("\n".to_string()))];
+    context.add_intrinsic("print", Function::native(print_params, |context, arguments| {
+        context.write(&arguments[0].to_string(), &arguments[1].to_string());
+        Ok(EvalResult::Null)
+    }));
+
+    // `rnd` and `shuffle` draw from the same sequence.
+    let random = Rc::new(Cell::new(seed_from_clock()));
+    add_math(context, random.clone());
+    add_strings(context);
+    add_collections(context, random);
+}
+
+fn add_math(context: &mut Context, random: Rc<Cell<u64>>) {
+    add_unary(context, "abs", "x", f64::abs);
+    add_unary(context, "acos", "x", f64::acos);
+    add_unary(context, "asin", "x", f64::asin);
+    add_unary(context, "ceil", "x", f64::ceil);
+    add_unary(context, "cos", "radians", f64::cos);
+    add_unary(context, "floor", "x", f64::floor);
+    add_unary(context, "sin", "radians", f64::sin);
+    add_unary(context, "sqrt", "x", f64::sqrt);
+    add_unary(context, "tan", "radians", f64::tan);
+    // Unlike `f64::signum`, the sign of 0 is 0.
+    add_unary(context, "sign", "x", |x| if x == 0.0 || x.is_nan() { 0.0 } else { x.signum() });
+
+    context.add_intrinsic("pi", Function::native(Vec::new(), |_, _| Ok(EvalResult::Number(PI))));
+
+    // With one argument this is the arctangent of `y`; with two it's the angle of the point (x, y).
+    context.add_intrinsic("atan", Function::native(vec![Param::new("y", zero()), Param::new("x", EvalResult::Number(1.0))], |_, arguments| {
+        let (y, x) = (number(&arguments[0]), number(&arguments[1]));
+        Ok(EvalResult::Number(if x == 1.0 { y.atan() } else { y.atan2(x) }))
+    }));
+
+    context.add_intrinsic("log", Function::native(vec![Param::new("x", zero()), Param::new("base", EvalResult::Number(10.0))], |_, arguments| {
+        let (x, base) = (number(&arguments[0]), number(&arguments[1]));
+        Ok(EvalResult::Number(if (base - E).abs() < 0.000001 { x.ln() } else { x.ln() / base.ln() }))
+    }));
+
+    // Rounds half away from zero.  A negative number of places rounds to tens, hundreds and so on.
+    context.add_intrinsic("round", Function::native(vec![Param::new("x", zero()), Param::new("decimalPlaces", zero())], |_, arguments| {
+        let (x, places) = (number(&arguments[0]), number(&arguments[1]) as i32);
+        let result = if places >= 0 {
+            let scale = 10f64.powi(places.min(15));
+            (x * scale).round() / scale
+        } else {
+            let scale = 10f64.powi(-places);
+            (x / scale).round() * scale
+        };
+        Ok(EvalResult::Number(result))
+    }));
+
+    add_bitwise(context, "bitAnd", |i, j| i & j);
+    add_bitwise(context, "bitOr", |i, j| i | j);
+    add_bitwise(context, "bitXor", |i, j| i ^ j);
+
+    // A number from 0 up to (but not including) 1.  Passing a seed restarts the sequence, so it can be repeated.
+    context.add_intrinsic("rnd", Function::native(vec![param("seed")], move |_, arguments| {
+        if let EvalResult::Number(seed) = arguments[0] {
+            random.set(seed_from(seed.to_bits()));
+        }
+        Ok(EvalResult::Number(next_random(&random)))
+    }));
+}
+
+// These take the value they work on as `self`, so they can be called either way: `upper(s)` or `s.upper`.
+fn add_strings(context: &mut Context) {
+    let string = context.types.string.clone();
+    let strings = [&string];
+
+    add_method(context, &strings, "upper", vec![this()], |_, arguments| match &arguments[0] {
+        EvalResult::String(s) => Ok(EvalResult::String(s.to_uppercase())),
+        other => Ok(other.clone()),
+    });
+
+    add_method(context, &strings, "lower", vec![this()], |_, arguments| match &arguments[0] {
+        EvalResult::String(s) => Ok(EvalResult::String(s.to_lowercase())),
+        other => Ok(other.clone()),
+    });
+
+    // An empty delimiter splits between every character.  A positive `maxCount` limits the number of pieces, with the
+    // last one holding the rest of the string.
+    let split_params = vec![this(), Param::new("delimiter", EvalResult::String(" ".to_string())), Param::new("maxCount", EvalResult::Number(-1.0))];
+    add_method(context, &strings, "split", split_params, |_, arguments| {
+        let s = match &arguments[0] {
+            EvalResult::String(s) => s,
+            _ => return Ok(EvalResult::Null),
+        };
+        let delimiter = arguments[1].to_string();
+        let max_count = match arguments[2] {
+            EvalResult::Number(n) if n > 0.0 => n as usize,
+            _ => usize::MAX,
+        };
+
+        let pieces: Vec<&str> = if delimiter.is_empty() {
+            let mut pieces: Vec<&str> = s.char_indices().map(|(i, c)| &s[i..i + c.len_utf8()]).collect();
+            if pieces.len() > max_count {
+                let (start, _) = s.char_indices().nth(max_count - 1).unwrap();
+                pieces.truncate(max_count - 1);
+                pieces.push(&s[start..]);
+            }
+            pieces
+        } else {
+            s.splitn(max_count, delimiter.as_str()).collect()
+        };
+        Ok(EvalResult::new_list(pieces.into_iter().map(|piece| EvalResult::String(piece.to_string())).collect()))
+    });
+
+    // Strings that don't hold a number count as 0.
+    add_method(context, &strings, "val", vec![Param::new("self", zero())], |_, arguments| match &arguments[0] {
+        EvalResult::Number(n) => Ok(EvalResult::Number(*n)),
+        EvalResult::String(s) => Ok(EvalResult::Number(s.trim().parse().unwrap_or(0.0))),
+        _ => Ok(EvalResult::Null),
+    });
+
+    // The code point of the first character of the value's string form.
+    add_method(context, &strings, "code", vec![this()], |_, arguments| match &arguments[0] {
+        EvalResult::Null => Ok(zero()),
+        value => Ok(value.to_string().chars().next().map_or(EvalResult::Null, |c| EvalResult::Number(c as u32 as f64))),
+    });
+
+    add_method(context, &strings, "str", vec![Param::new("self", EvalResult::String(String::new()))], |_, arguments| match &arguments[0] {
+        EvalResult::Null => Ok(EvalResult::String(String::new())),
+        value => Ok(EvalResult::String(value.to_string())),
+    });
+
+    add_method(context, &strings, "hash", vec![this()], |_, arguments| {
+        Ok(EvalResult::Number(arguments[0].hash_code() as i32 as f64))
+    });
+
+    // Anything that isn't a valid code point gives the character with code 0.
+    context.add_intrinsic("char", Function::native(vec![Param::new("codePoint", EvalResult::Number(65.0))], |_, arguments| {
+        let c = match arguments[0] {
+            EvalResult::Number(n) if n >= 0.0 => char::from_u32(n as u32).unwrap_or('\0'),
+            _ => '\0',
+        };
+        Ok(EvalResult::String(c.to_string()))
+    }));
+}
+
+// The functions shared by strings, lists and maps.  Lists and maps are changed in place.
+fn add_collections(context: &mut Context, random: Rc<Cell<u64>>) {
+    let (string, list, map) = (context.types.string.clone(), context.types.list.clone(), context.types.map.clone());
+    let all = [&string, &list, &map];
+    let sequences = [&string, &list];
+    let containers = [&list, &map];
+
+    add_method(context, &all, "len", vec![this()], |_, arguments| match &arguments[0] {
+        EvalResult::String(s) => Ok(EvalResult::Number(char_count(s) as f64)),
+        EvalResult::List(items) => Ok(EvalResult::Number(items.borrow().len() as f64)),
+        EvalResult::Map(map) => Ok(EvalResult::Number(map.borrow().len() as f64)),
+        _ => Ok(EvalResult::Null),
+    });
+
+    // The search starts just past `after`: an index for strings and lists, counting from the end if it's negative, or a
+    // key for maps.
+    add_method(context, &all, "indexOf", vec![this(), param("value"), param("after")], |_, arguments| {
+        let (value, after) = (&arguments[1], &arguments[2]);
+        match &arguments[0] {
+            EvalResult::String(_) if *value == EvalResult::Null => Ok(EvalResult::Null),
+            EvalResult::String(s) => {
+                let start = search_start(after, char_count(s));
+                let offset = char_offset(s, start);
+                let found = s[offset..].find(&value.to_string()).map(|position| char_count(&s[..offset + position]));
+                Ok(found.map_or(EvalResult::Null, |position| EvalResult::Number(position as f64)))
+            },
+            EvalResult::List(items) => {
+                let items = items.borrow();
+                let start = search_start(after, items.len());
+                let found = items.iter().skip(start).position(|item| item == value);
+                Ok(found.map_or(EvalResult::Null, |position| EvalResult::Number((start + position) as f64)))
+            },
+            EvalResult::Map(map) => {
+                let map = map.borrow();
+                let start = match after {
+                    EvalResult::Null => 0,
+                    after => map.iter().position(|(key, _)| key == after).map_or(map.len(), |position| position + 1),
+                };
+                let found = map.iter().skip(start).find(|(_, item)| *item == value).map(|(key, _)| key.clone());
+                Ok(found.unwrap_or(EvalResult::Null))
+            },
+            _ => Ok(EvalResult::Null),
+        }
+    });
+
+    add_method(context, &all, "hasIndex", vec![this(), param("index")], |_, arguments| match (&arguments[0], &arguments[1]) {
+        (EvalResult::String(s), EvalResult::Number(index)) => Ok(truth(resolve_index(*index, char_count(s)).is_some())),
+        (EvalResult::List(items), EvalResult::Number(index)) => Ok(truth(resolve_index(*index, items.borrow().len()).is_some())),
+        (EvalResult::String(_) | EvalResult::List(_), _) => Ok(truth(false)),
+        (EvalResult::Map(map), key) => Ok(truth(map.borrow().contains_key(key))),
+        _ => Ok(EvalResult::Null),
+    });
+
+    add_method(context, &all, "indexes", vec![this()], |_, arguments| match &arguments[0] {
+        EvalResult::String(s) => Ok(numbers_below(char_count(s))),
+        EvalResult::List(items) => Ok(numbers_below(items.borrow().len())),
+        EvalResult::Map(map) => Ok(EvalResult::new_list(map.borrow().iter().map(|(key, _)| key.clone()).collect())),
+        _ => Ok(EvalResult::Null),
+    });
+
+    add_method(context, &all, "values", vec![this()], |_, arguments| match &arguments[0] {
+        EvalResult::String(s) => Ok(EvalResult::new_list(s.chars().map(|c| EvalResult::String(c.to_string())).collect())),
+        EvalResult::Map(map) => Ok(EvalResult::new_list(map.borrow().iter().map(|(_, value)| value.clone()).collect())),
+        other => Ok(other.clone()),
+    });
+
+    // Replaces items of a list or values of a map in place, or occurrences within a string.  A `maxCount` limits how
+    // many are replaced.
+    add_method(context, &all, "replace", vec![this(), param("oldval"), param("newval"), param("maxCount")], |_, arguments| {
+        let (old, new) = (&arguments[1], &arguments[2]);
+        let max_count = match arguments[3] {
+            EvalResult::Number(n) => n.max(0.0) as usize,
+            _ => usize::MAX,
+        };
+        match &arguments[0] {
+            EvalResult::String(s) => {
+                let old = old.to_string();
+                if old.is_empty() {
+                    return Err("replace: oldval argument is empty".to_string());
+                }
+                Ok(EvalResult::String(s.replacen(&old, &new.to_string(), max_count)))
+            },
+            EvalResult::List(items) => {
+                // An item can contain the list itself, so compare them all before borrowing the list to change it.  The
+                // same goes for the values of a map.
+                let positions: Vec<usize> = items.borrow().iter().enumerate().filter(|(_, item)| *item == old).map(|(position, _)| position).take(max_count).collect();
+                let mut items = items.borrow_mut();
+                for position in positions {
+                    items[position] = new.clone();
+                }
+                Ok(arguments[0].clone())
+            },
+            EvalResult::Map(map) => {
+                let positions: Vec<usize> = map.borrow().iter().enumerate().filter(|(_, (_, value))| *value == old).map(|(position, _)| position).take(max_count).collect();
+                let mut map = map.borrow_mut();
+                for position in positions {
+                    map.set_value_at(position, new.clone());
+                }
+                Ok(arguments[0].clone())
+            },
+            EvalResult::Null => Err("argument to 'replace' must not be null".to_string()),
+            _ => Err("Type Error: 'replace' requires map, list, or string".to_string()),
+        }
+    });
+
+    // Takes the first occurrence of `k` out of a string, the item at index `k` out of a list, or the key `k` out of a
+    // map.  For a map, the result says whether the key was there.
+    add_method(context, &all, "remove", vec![this(), param("k")], |_, arguments| match (&arguments[0], &arguments[1]) {
+        (EvalResult::String(s), k) => Ok(EvalResult::String(s.replacen(&k.to_string(), "", 1))),
+        (EvalResult::List(items), EvalResult::Number(index)) => {
+            let mut items = items.borrow_mut();
+            match resolve_index(*index, items.len()) {
+                Some(position) => {
+                    items.remove(position);
+                    Ok(EvalResult::Null)
+                },
+                None => Err(format!("Index Error (list index {} out of range)", index)),
+            }
+        },
+        (EvalResult::List(_), _) => Err("Index Error (list index must be a number)".to_string()),
+        (EvalResult::Map(map), k) => Ok(truth(value_map::remove_entry(map, k).is_some())),
+        (EvalResult::Null, _) => Err("argument to 'remove' must not be null".to_string()),
+        _ => Err("Type Error: 'remove' requires map, list, or string".to_string()),
+    });
+
+    // A negative index counts from the end, where -1 inserts after the last item.
+    add_method(context, &sequences, "insert", vec![this(), param("index"), param("value")], |_, arguments| {
+        let index = match arguments[1] {
+            EvalResult::Number(index) => index,
+            _ => return Err("Index Error (index must be a number)".to_string()),
+        };
+        let position = |len: usize| {
+            let mut position = index as i64;
+            if position < 0 {
+                position += len as i64 + 1;
+            }
+            (0..=len as i64).contains(&position).then_some(position as usize)
+        };
+        match &arguments[0] {
+            EvalResult::String(s) => {
+                let position = position(char_count(s)).ok_or_else(|| format!("Index Error (string index {} out of range)", index))?;
+                let mut s = s.clone();
+                s.insert_str(char_offset(&s, position), &arguments[2].to_string());
+                Ok(EvalResult::String(s))
+            },
+            EvalResult::List(items) => {
+                let len = items.borrow().len();
+                let position = position(len).ok_or_else(|| format!("Index Error (list index {} out of range)", index))?;
+                items.borrow_mut().insert(position, arguments[2].clone());
+                Ok(arguments[0].clone())
+            },
+            EvalResult::Null => Err("insert called on null".to_string()),
+            _ => Err("insert called on invalid type".to_string()),
+        }
+    });
+
+    add_method(context, &sequences, "slice", vec![this(), Param::new("from", zero()), param("to")], |_, arguments| match &arguments[0] {
+        EvalResult::String(_) | EvalResult::List(_) => slice(&arguments[0], Some(&arguments[1]), Some(&arguments[2])),
+        _ => Ok(EvalResult::Null),
+    });
+
+    // Pushing onto a map adds the value as a key, as if the map were a set.
+    add_method(context, &containers, "push", vec![this(), param("value")], |_, arguments| match &arguments[0] {
+        EvalResult::List(items) => {
+            items.borrow_mut().push(arguments[1].clone());
+            Ok(arguments[0].clone())
+        },
+        EvalResult::Map(map) => {
+            value_map::set_entry(map, arguments[1].clone(), EvalResult::Number(1.0));
+            Ok(arguments[0].clone())
+        },
+        _ => Ok(EvalResult::Null),
+    });
+
+    // `pop` takes the last item of a list, and `pull` the first.  Both take the first key out of a map.
+    add_method(context, &containers, "pop", vec![this()], |_, arguments| match &arguments[0] {
+        EvalResult::List(items) => Ok(items.borrow_mut().pop().unwrap_or(EvalResult::Null)),
+        EvalResult::Map(map) => Ok(take_first_key(map)),
+        _ => Ok(EvalResult::Null),
+    });
+
+    add_method(context, &containers, "pull", vec![this()], |_, arguments| match &arguments[0] {
+        EvalResult::List(items) => {
+            let mut items = items.borrow_mut();
+            Ok(if items.is_empty() { EvalResult::Null } else { items.remove(0) })
+        },
+        EvalResult::Map(map) => Ok(take_first_key(map)),
+        _ => Ok(EvalResult::Null),
+    });
+
+    // Anything that isn't a number adds nothing.
+    add_method(context, &containers, "sum", vec![this()], |_, arguments| match &arguments[0] {
+        EvalResult::List(items) => Ok(EvalResult::Number(items.borrow().iter().map(number).sum())),
+        EvalResult::Map(map) => Ok(EvalResult::Number(map.borrow().iter().map(|(_, value)| number(value)).sum())),
+        _ => Ok(zero()),
+    });
+
+    add_method(context, &[&list], "join", vec![this(), Param::new("delimiter", EvalResult::String(" ".to_string()))], |_, arguments| match &arguments[0] {
+        EvalResult::List(items) => {
+            let items: Vec<String> = items.borrow().iter().map(|item| item.to_string()).collect();
+            Ok(EvalResult::String(items.join(&arguments[1].to_string())))
+        },
+        other => Ok(other.clone()),
+    });
+
+    // The sort is stable, and can order a list of maps or lists by one of their entries.
+    add_method(context, &[&list], "sort", vec![this(), param("byKey"), Param::new("ascending", EvalResult::Number(1.0))], |_, arguments| {
+        let items = match &arguments[0] {
+            EvalResult::List(items) => items,
+            other => return Ok(other.clone()),
+        };
+        let (by_key, ascending) = (&arguments[1], is_truthy(arguments[2].clone()));
+
+        let mut keyed: Vec<(EvalResult, EvalResult)> = items.borrow().iter().map(|item| {
+            let key = match (by_key, item) {
+                (EvalResult::Null, _) => item.clone(),
+                (key, EvalResult::Map(map)) => map.borrow().get(key).cloned().unwrap_or(EvalResult::Null),
+                (EvalResult::Number(index), EvalResult::List(entries)) => {
+                    let entries = entries.borrow();
+                    resolve_index(*index, entries.len()).map_or(EvalResult::Null, |position| entries[position].clone())
+                },
+                _ => EvalResult::Null,
+            };
+            (key, item.clone())
+        }).collect();
+        keyed.sort_by(|(a, _), (b, _)| if ascending { compare_for_sort(a, b) } else { compare_for_sort(b, a) });
+
+        *items.borrow_mut() = keyed.into_iter().map(|(_, item)| item).collect();
+        Ok(arguments[0].clone())
+    });
+
+    // Shuffling a map shuffles which value goes with which key.
+    add_method(context, &containers, "shuffle", vec![this()], move |_, arguments| {
+        match &arguments[0] {
+            EvalResult::List(items) => shuffle(&mut items.borrow_mut(), &random),
+            EvalResult::Map(map) => {
+                let mut values: Vec<EvalResult> = map.borrow().iter().map(|(_, value)| value.clone()).collect();
+                shuffle(&mut values, &random);
+                let mut map = map.borrow_mut();
+                for (position, value) in values.into_iter().enumerate() {
+                    map.set_value_at(position, value);
+                }
+            },
+            _ => {},
+        }
+        Ok(EvalResult::Null)
+    });
+
+    // The numbers from `from` to `to`, inclusive.  The step defaults to 1 or -1, whichever heads towards `to`; a step
+    // that heads the other way gives an empty list.
+    context.add_intrinsic("range", Function::native(vec![Param::new("from", zero()), Param::new("to", zero()), param("step")], |_, arguments| {
+        let (from, to) = (number(&arguments[0]), number(&arguments[1]));
+        let step = match arguments[2] {
+            EvalResult::Number(step) => step,
+            _ => if to >= from { 1.0 } else { -1.0 },
+        };
+        if step == 0.0 {
+            return Err("range() error (step==0)".to_string());
+        }
+
+        let count = ((to - from) / step).floor() + 1.0;
+        if count > MAX_LIST_SIZE as f64 {
+            return Err("list too large".to_string());
+        }
+        let count = count.max(0.0) as usize;
+        Ok(EvalResult::new_list((0..count).map(|i| EvalResult::Number(from + step * i as f64)).collect()))
+    }));
+}
+
+// Add a function that can be called on its own, and as a method of values of the types in `type_maps`.
+fn add_method(context: &mut Context, type_maps: &[&MapRef], name: &str, params: Vec<Param>, body: impl Fn(&mut Context, Vec<EvalResult>) -> Result<EvalResult, String> + 'static) {
+    let function = context.add_intrinsic(name, Function::native(params, body));
+    for type_map in type_maps {
+        type_map.borrow_mut().set(EvalResult::String(name.to_string()), function.clone());
+    }
+}
+
+// Where a search begins, given the `after` argument of `indexOf`.
+fn search_start(after: &EvalResult, len: usize) -> usize {
+    match after {
+        EvalResult::Number(after) => {
+            // -1 means before the first item, so only lower numbers count from the end.  Anything past the end finds
+            // nothing.
+            let mut after = *after as i64;
+            if after < -1 {
+                after += len as i64;
+            }
+            (after.clamp(-1, len as i64) + 1) as usize
+        },
+        _ => 0,
+    }
+}
+
+// The list [0, 1, ... len - 1].
+fn numbers_below(len: usize) -> EvalResult {
+    EvalResult::new_list((0..len).map(|i| EvalResult::Number(i as f64)).collect())
+}
+
+fn take_first_key(map: &MapRef) -> EvalResult {
+    let entry = map.borrow_mut().remove_first();
+    entry.map_or(EvalResult::Null, |(key, _)| key)
+}
+
+// How `sort` orders values: if either is a string they're compared as strings, numbers are compared by value, and
+// `null` goes last.  Anything else counts as equal, so it keeps its place.
+fn compare_for_sort(a: &EvalResult, b: &EvalResult) -> Ordering {
+    match (a, b) {
+        (EvalResult::Null, EvalResult::Null) => Ordering::Equal,
+        (EvalResult::Null, _) => Ordering::Greater,
+        (_, EvalResult::Null) => Ordering::Less,
+        (EvalResult::String(_), _) | (_, EvalResult::String(_)) => a.to_string().cmp(&b.to_string()),
+        (EvalResult::Number(a), EvalResult::Number(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+        _ => Ordering::Equal,
+    }
+}
+
+// Fisher-Yates.
+fn shuffle(items: &mut [EvalResult], random: &Cell<u64>) {
+    for i in (1..items.len()).rev() {
+        let j = (next_random(random) * (i + 1) as f64) as usize;
+        items.swap(i, j);
+    }
+}
+
+fn add_unary(context: &mut Context, name: &str, param: &str, operation: fn(f64) -> f64) {
+    context.add_intrinsic(name, Function::native(vec![Param::new(param, zero())], move |_, arguments| {
+        Ok(EvalResult::Number(operation(number(&arguments[0]))))
+    }));
+}
+
+// The bitwise functions work on the numbers as whole numbers stored as a sign and a magnitude, rather than in two's
+// complement.  The sign is combined like one more bit, so `bitAnd(-5, 3)` is 1 and `bitOr(-5, 3)` is -7.
+fn add_bitwise(context: &mut Context, name: &str, operation: fn(u64, u64) -> u64) {
+    context.add_intrinsic(name, Function::native(vec![Param::new("i", zero()), Param::new("j", zero())], move |_, arguments| {
+        let (i, j) = (number(&arguments[0]) as i64, number(&arguments[1]) as i64);
+        let magnitude = operation(i.unsigned_abs(), j.unsigned_abs()) as f64;
+        let negative = operation((i < 0) as u64, (j < 0) as u64) != 0;
+        Ok(EvalResult::Number(if negative { -magnitude } else { magnitude }))
+    }));
+}
+
+fn zero() -> EvalResult {
+    EvalResult::Number(0.0)
+}
+
+// A parameter without a default.
+fn param(name: &str) -> Param {
+    Param::new(name, EvalResult::Null)
+}
+
+// The parameter a method uses for the value it's called on.
+fn this() -> Param {
+    param("self")
+}
+
+fn truth(value: bool) -> EvalResult {
+    EvalResult::Number(if value { 1.0 } else { 0.0 })
+}
+
+// Math functions treat anything that isn't a number as 0.
+fn number(value: &EvalResult) -> f64 {
+    match value {
+        EvalResult::Number(n) => *n,
+        _ => 0.0,
+    }
+}
+
+fn seed_from_clock() -> u64 {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_nanos() as u64);
+    seed_from(nanos)
+}
+
+// Spread the bits of `value` out (SplitMix64) so that similar seeds still give unrelated sequences.
+// xorshift gets stuck on a state of 0, so that's never returned.
+fn seed_from(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9E3779B97F4A7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    (z ^ (z >> 31)).max(1)
+}
+
+// xorshift64*, using the top 53 bits of each output as the fraction.
+fn next_random(state: &Cell<u64>) -> f64 {
+    let mut x = state.get();
+    x ^= x >> 12;
+    x ^= x << 25;
+    x ^= x >> 27;
+    state.set(x);
+    (x.wrapping_mul(0x2545F4914F6CDD1D) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::run;
+
+    #[test]
+    fn test_math() {
+        test_output("print round(pi, 2)\nprint round(12345, -3)\nprint round(-2.5)", &["3.14", "12000", "-3"]);
+        test_output("print atan(1, -1) / pi\nprint round(atan(0.5), 3)", &["0.75", "0.464"]);
+        test_output("print log(100)\nprint log(2048, 2)\nprint round(log(10, 2.7182818), 3)", &["2", "11", "2.303"]);
+        test_output("print sign(-22)\nprint sign(0)\nprint sign(\"a\")\nprint abs(-3) + floor(2.5) + ceil(2.5)", &["-1", "0", "0", "8"]);
+        test_output("print sqrt(1764)\nprint sqrt(-1)", &["42", "NaN"]);
+        test_output("print bitAnd(14, 7)\nprint bitOr(14, 7)\nprint bitXor(14, 7)\nprint bitAnd(-224883, 222678)\nprint bitOr(-12345, 54321)\nprint bitXor(-23453455467, -76598387347)", &["6", "15", "9", "222290", "-62521", "88591169272"]);
+    }
+
+    #[test]
+    fn test_rnd() {
+        test_output("x = rnd\nprint x >= 0 and x < 1\nprint @rnd", &["1", "FUNCTION(seed)"]);
+        test_output("a = rnd(42)\nb = rnd\nprint rnd(42) == a and rnd == b and a != b", &["1"]);
+    }
+
+    #[test]
+    fn test_strings() {
+        test_output("s = \"hElLo\"\nprint s.upper + upper(s) + s.lower\nprint s.len + len(\"\")", &["HELLOHELLOhello", "5"]);
+        test_output("s = \"hello world\"\nprint s.indexOf(\"o\")\nprint s.indexOf(\"o\", 4)\nprint s.indexOf(\"o\", 7)\nprint s[\"indexOf\"](\"l\", -3)", &["4", "7", "null", "9"]);
+        test_output("print \"abc\".indexOf(\"a\", 1e19)\nprint [1, 2].indexOf(1, 1e19)\nprint [1, 2].indexOf(2, -1e19)", &["null", "null", "1"]);
+        test_output("s = \"abcde\"\nprint s.hasIndex(4) + s.hasIndex(-5)\nprint s.hasIndex(5) + s.hasIndex(\"a\")", &["2", "0"]);
+        test_output("print \"a b  c\".split\nprint split(\"a,b,c\", \",\", 2)\nprint \"abc\".split(\"\")", &["[\"a\", \"b\", \"\", \"c\"]", "[\"a\", \"b,c\"]", "[\"a\", \"b\", \"c\"]"]);
+        test_output("s = \"banana\"\nprint s.replace(\"a\", \"o\")\nprint s.replace(\"a\", \"o\", 2)\nprint s.remove(\"an\")\nprint s.insert(-1, \"s\")", &["bonono", "bonona", "bana", "bananas"]);
+        test_output("print slice(\"HELLO\", 2, 4)\nprint \"HELLO\".slice(-4, -2)\nprint slice([0, 10, 20, 30], 1, -1)", &["LL", "EL", "[10, 20]"]);
+        test_output("print \"42\".val + val(\" -0.75 \") + val(\"Hello\")\nprint val(null)", &["41.25", "null"]);
+        test_output("print \"A\".code + code([1]) + code(null)\nprint code(\"\")\nprint char(64) + char(32) + char(65) + code(char(-1))", &["156", "null", "@ A0"]);
+        test_output("s = \"naïve café\"\nprint s.len + s.indexOf(\"é\")\nprint s.indexes.len + s.hasIndex(9)\nprint s.insert(3, \"-\") + s.indexOf(\"a\", 1)", &["19", "11", "naï-ve café7"]);
+        test_output("print str(42.0) + str(null) + \"x\".str\nprint hash(\"abc\") == hash(\"ab\" + \"c\")", &["42x", "1"]);
+    }
+
+    #[test]
+    fn test_string_type_map() {
+        test_output("string.shout = function\n return self.upper + \"!\"\nend function\nprint \"hey\".shout\nprint \"hey\" isa string", &["HEY!", "1"]);
+        test_output("s = \"abc\"\ns.replace \"\", \"x\"", &["Runtime Error: replace: oldval argument is empty [line 2]"]);
+    }
+
+    #[test]
+    fn test_lists() {
+        test_output("l = [3, 1, 2]\nl.push 5\nprint l.pop + l.pull\nl.sort\nprint l\nl.insert 0, 9\nl.remove -1\nprint l.join(\"-\")", &["8", "[1, 2]", "9-1"]);
+        test_output("l = [{\"a\":2}, {\"a\":1}]\nprint l.sort(\"a\", 0)\nprint [2, \"b\", null, 1].sort", &["[{\"a\": 2}, {\"a\": 1}]", "[1, 2, \"b\", null]"]);
+        test_output("print range(4)\nprint range(10, 9, 1)\nprint range(0, 0, -1)\nprint sum([1, 2, 3.5])", &["[4, 3, 2, 1, 0]", "[]", "[0]", "6.5"]);
+        test_output("l = [1]\nl.push l\nl.replace l, 0\nprint l\nl = [[1]]\nl.replace l, 0\nprint l", &["[1, 0]", "[[1]]"]);
+        test_output("print [1, 2, 1].indexOf(1, 0)\nprint [1, 2].hasIndex(-2)\nprint [5, 6].indexes", &["2", "1", "[0, 1]"]);
+    }
+
+    #[test]
+    fn test_maps() {
+        test_output("m = {\"a\": 1, \"b\": 2}\nprint m.indexes + m.values\nprint m.indexOf(2)\nprint m.remove(\"a\") + m.remove(\"a\")\nprint m.len", &["[\"a\", \"b\", 1, 2]", "b", "1", "1"]);
+        test_output("m = {}\nm.push 4\nprint m\nprint m.pull\nprint m.pop", &["{4: 1}", "4", "null"]);
+        test_output("m = {}\nm.push m\nm.replace 1, m\nm.shuffle\nprint m.remove(m) + m.len\nprint m.pop == m\nprint m.len", &["1", "1", "0"]);
+    }
+
+    #[test]
+    fn test_print() {
+        test_output("print \"Hello\", \"-\"\nprint \"Another\", \"\"\nprint \" & another\"\nprint \"END\"", &["Hello-Another & another", "END"]);
+        test_output("print 1, char(10) * 2\nprint [2], \", \"\nprint", &["1", "", "[2], "]);
+        test_output("print \"no newline\", \"\"", &["no newline"]);
+    }
+
+    fn test_output(code: &str, expected: &[&str]) {
+        assert_eq!(run(code).1, expected, "{}", code);
+    }
+}
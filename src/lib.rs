@@ -1,33 +1,45 @@
 // src/lib.rs
 
+mod context;
 mod environment;
 mod error;
 mod error_reporter;
 mod error_stage;
 mod eval_result;
 mod expression;
+mod function;
+mod intrinsics;
 mod parser;
 mod scanner;
+mod span;
 mod statement;
 mod token;
 mod token_type;
+#[cfg(test)]
+mod test_support;
+mod value_map;
 
 use environment::Environment;
 use expression::eval_stmts;
 use scanner::Scanner;
 use error_reporter::ErrorReporter;
 
+pub use context::Context;
 pub use error::Error;
 pub use eval_result::EvalResult;
 pub use expression::{Expr, format_ast};
+pub use function::{Function, Outer, Param};
+pub use span::Span;
 pub use token::Token;
 pub use token_type::TokenType;
+pub use value_map::ValueMap;
 
 use crate::parser::Parser;
 
 pub struct Miniscript {
     pub globals: Environment,
-    
+    pub context: Context,
+
     pub had_error: bool,
     pub had_runtime_error: bool,
 }
@@ -36,12 +48,34 @@ impl Miniscript {
     pub fn new() -> Miniscript {
         Self {
             globals: Environment::new_root(),
+            context: Context::new(),
             had_error: false,
             had_runtime_error: false,
         }
     }
 
+    // Send everything the script prints to `output` instead of stdout, one line per call.
+    pub fn set_output(&mut self, output: impl FnMut(&str) + 'static) {
+        self.context.set_output(output);
+    }
+
+    // Make a Rust function callable from scripts as `name`, alongside the built-in ones.  A script variable of the same
+    // name hides it.  Each call gets one argument per parameter, in order, with the parameter's default standing in
+    // for any the script leaves out.  Returning `Err` raises a runtime error with that message.
+    pub fn add_intrinsic(&mut self, name: &str, params: Vec<Param>, body: impl Fn(&mut Context, Vec<EvalResult>) -> Result<EvalResult, String> + 'static) {
+        self.context.add_intrinsic(name, Function::native(params, body));
+    }
+
     pub fn run(&mut self, code: &str) -> bool {
+        self.execute(code, false)
+    }
+
+    // Run a line typed at the prompt.  Unlike `run`, the value of the last expression is echoed back.
+    pub fn run_line(&mut self, code: &str) -> bool {
+        self.execute(code, true)
+    }
+
+    fn execute(&mut self, code: &str, implicit_output: bool) -> bool {
         let mut reporter = ErrorReporter::new();
 
         // // Placeholder for your language execution logic
@@ -55,37 +89,44 @@ impl Miniscript {
         //     println!("Token: {:?}", token);
         // }
 
-        let mut parser = Parser::new(scanner.tokens);
-        
-        let stmts = match parser.parse(&mut reporter) {
-            Ok(stmts) => stmts,
-            _ => {
-                /* Errors will be handled later. */
-                Vec::new()
-            },
+        // Errors will be handled later.  Only the first is reported, so a bad token isn't parsed as well.
+        let stmts = if reporter.had_error() {
+            Vec::new()
+        } else {
+            Parser::new(scanner.tokens).parse(&mut reporter).unwrap_or_default()
         };
 
+        // A program with compile errors doesn't run at all, not even the statements that parsed.
+        if reporter.had_error() {
+            self.had_error = true;
+            self.had_runtime_error = false;
+            reporter.dump(&mut self.context);
+            return true;
+        }
+
         // // Print all statements to stdout.
         // for stmt in &stmts {
         //     println!("Statement: {:?}", stmt);
         // }
 
-        let result = match eval_stmts(&mut self.globals, &stmts, &mut reporter) {
+        let result = match eval_stmts(&mut self.context, &mut self.globals, &stmts, &mut reporter) {
             Ok(result) => result,
             _ => {
                 /* Errors will be handled later. */
                 EvalResult::Null
             }
         };
-        if result != EvalResult::Null {
-            println!("{}", result);
+        self.context.flush();
+        if implicit_output && result != EvalResult::Null {
+            self.context.print(&result.to_string());
+            self.context.last_result = Some(result);
         }
         
         self.had_error = reporter.had_error();
         self.had_runtime_error = reporter.had_runtime_error();
 
         if reporter.had_error() || reporter.had_runtime_error() {
-            reporter.dump();
+            reporter.dump(&mut self.context);
         }
         reporter.had_error()
     }
@@ -102,6 +143,108 @@ impl Miniscript {
         let result = self.run(&contents);
         println!("Result: {}", result);
 
-        return result;
+        result
+    }
+}
+
+impl Default for Miniscript {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use crate::{test_support::{capture_output, run}, Miniscript};
+
+    #[test]
+    fn test_return_outside_function() {
+        let (miniscript, output) = run("print 1\nreturn 3\nprint 2");
+        assert!(!miniscript.had_error);
+        assert_eq!(output, ["1"]);
+    }
+
+    #[test]
+    fn test_compile_error_stops_the_program() {
+        let (miniscript, output) = run("print 1\nx = )\nprint y");
+        assert!(miniscript.had_error && !miniscript.had_runtime_error);
+        assert_eq!(output, ["Compiler Error: Expected a primary expression. [line 2]"]);
+    }
+
+    #[test]
+    fn test_loop_in_single_line_if() {
+        let (miniscript, output) = run("print 1\nif true then for i in \"abc\"");
+        assert!(miniscript.had_error);
+        assert_eq!(output, ["Compiler Error: loop is invalid within single-line 'if' [line 2]"]);
+        let (_, output) = run("if true then while true");
+        assert_eq!(output, ["Compiler Error: loop is invalid within single-line 'if' [line 1]"]);
+    }
+
+    #[test]
+    fn test_loop_jump_outside_loop() {
+        let (miniscript, output) = run("break");
+        assert!(miniscript.had_error);
+        assert_eq!(output, ["Compiler Error: 'break' without open loop block [line 1]"]);
+        let (_, output) = run("while 1\n break\nend while\nif 1 then continue");
+        assert_eq!(output, ["Compiler Error: 'continue' without open loop block [line 4]"]);
+    }
+
+    #[test]
+    fn test_size_limits() {
+        let (_, output) = run("print [1] * 1e18");
+        assert_eq!(output, ["Runtime Error: list too large [line 1]"]);
+        let (_, output) = run("print \"ab\" * 1e12");
+        assert_eq!(output, ["Runtime Error: string too large [line 1]"]);
+    }
+
+    #[test]
+    fn test_runaway_recursion() {
+        // These run on the test thread's default stack.
+        let (miniscript, output) = run("f = function(n)\n if n then return f(n + 1) + 1\nend function\nf 1\nprint \"unreached\"");
+        assert!(miniscript.had_runtime_error);
+        assert_eq!(output, ["Runtime Error: Stack Overflow [line 2]"]);
+
+        // Calls nested in blocks take more stack each.
+        let (_, output) = run("f = function(n)\n for i in [1]\n  while 1\n   if n then return f(n + 1) + [n][0]\n  end while\n end for\nend function\nf 1");
+        assert_eq!(output, ["Runtime Error: Stack Overflow [line 4]"]);
+
+        assert_eq!(miniscript.context.call_depth, 0);
+
+        let (_, output) = run("f = function(n)\n if n > 0 then return f(n - 1) + 1\n return 0\nend function\nprint f(40)");
+        assert_eq!(output, ["40"]);
+    }
+
+    #[test]
+    fn test_assign_to_scope() {
+        let (miniscript, output) = run("f = function\n locals = {}\nend function\nf\nprint 1");
+        assert!(miniscript.had_runtime_error);
+        assert_eq!(output, ["Runtime Error: Can't assign to locals [line 2]"]);
+        let (_, output) = run("globals = {}");
+        assert_eq!(output, ["Runtime Error: Can't assign to globals [line 1]"]);
+    }
+
+    #[test]
+    fn test_globals_are_freed() {
+        let (miniscript, _) = run("f = function(n)\n g = function\n  return n\n end function\n return @g\nend function\nh = f(1)\nm = {}\nm.f = @f");
+        let globals = Rc::downgrade(&miniscript.globals.globals);
+        drop(miniscript);
+        assert!(globals.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_last_result() {
+        let mut miniscript = Miniscript::new();
+        let output = capture_output(&mut miniscript);
+
+        // Only values shown at the prompt become `_`.  Assignments aren't shown, and `_` isn't a variable.
+        for line in ["1 + 2", "x = _ * 2", "x", "_ + 1", "print locals"] {
+            miniscript.run_line(line);
+        }
+        assert_eq!(output.take(), ["3", "6", "7", "{\"x\": 6}"]);
+
+        let (_, output) = run("x = 1\nx + 1\nprint locals\nprint _");
+        assert_eq!(output, ["{\"x\": 1}", "Runtime Error: Undefined Identifier: '_' is unknown in this context [line 4]"]);
     }
 }
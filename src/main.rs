@@ -46,7 +46,7 @@ impl MiniCmd {
                 break;
             }
     
-            let _output = self.interpreter.run(&input);
+            let _output = self.interpreter.run_line(&input);
             // println!("{}", output);
         }
     }
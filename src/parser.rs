@@ -1,6 +1,6 @@
-use std::{error::Error, fmt::{self, Display, Formatter}};
+use std::{error::Error, fmt::{self, Display, Formatter}, rc::Rc};
 
-use crate::{error_reporter::ErrorReporter, statement::Stmt, Expr, Token, TokenType};
+use crate::{error_reporter::ErrorReporter, statement::Stmt, Expr, Span, Token, TokenType};
 
 // Define a custom error that can be returned from a function.
 #[derive(Debug)]
@@ -28,6 +28,8 @@ pub struct Parser {
     tokens: Vec<Token>,
 
     current: i64,
+    // How many loops enclose the statement being parsed, so `break` and `continue` can be checked.
+    loop_depth: usize,
 }
 
 impl Parser {
@@ -35,6 +37,7 @@ impl Parser {
         Self {
             tokens,
             current: 0,
+            loop_depth: 0,
         }
     }
 
@@ -42,9 +45,12 @@ impl Parser {
         self.tokens[self.current as usize].clone()
     }
 
-    // fn peek_next(&self) -> Token {
-    //     self.tokens[(self.current + 1) as usize].clone()
-    // }
+    fn peek_next(&self) -> Token {
+        if self.is_at_end() {
+            return self.peek();
+        }
+        self.tokens[(self.current + 1) as usize].clone()
+    }
 
     fn is_at_end(&self) -> bool {
         self.peek().token_type == TokenType::EOF
@@ -67,7 +73,12 @@ impl Parser {
         if !self.is_at_end() {
             self.current += 1;
         }
-        return self.previous()
+        self.previous()
+    }
+
+    // The span from `start` through the last token consumed.
+    fn span_from(&self, start: &Span) -> Span {
+        start.to(&self.previous().span)
     }
 
     // The `match_token` function will take in one or more TokenTypes as parameters, then validate that the next token matches of of these token types.
@@ -90,40 +101,206 @@ impl Parser {
     fn program(&mut self, reporter: &mut ErrorReporter) -> Result<Vec<Stmt>, ParseError> {
         let mut stmts = Vec::new();
 
+        self.skip_empty_stmts();
         while !self.is_at_end() {
-            // let stmt = self.statement(reporter)?;
-            // stmts.push(stmt);
-
-            match self.statement(reporter) {
-                Ok(stmt) => {
-                    stmts.push(stmt);
-                },
-                Err(_e) => {
-                    // panic!("{:?}", e);
-                    self.synchronize();
-                }
-            }
+            // Parsing stops at the first compile error.  The ones after it are usually just knock-on effects.
+            stmts.push(self.statement(reporter)?);
+            self.skip_empty_stmts();
         }
 
         Ok(stmts)
     }
 
     fn statement(&mut self, reporter: &mut ErrorReporter) -> Result<Stmt, ParseError> {
-        if self.match_token(&[TokenType::Print]) {
-            return self.print_stmt(reporter);
+        if self.match_token(&[TokenType::If]) {
+            return self.if_stmt(self.previous(), reporter);
+        } else if self.match_token(&[TokenType::While]) {
+            return self.while_stmt(self.previous(), reporter);
+        } else if self.match_token(&[TokenType::For]) {
+            return self.for_stmt(self.previous(), reporter);
+        } else if self.check(TokenType::Else) {
+            return Err(self.error(self.peek(), "'else' without matching 'if'", reporter));
+        } else if self.check(TokenType::End) {
+            return Err(self.unmatched_end(reporter));
         // } else if self.peek().token_type == TokenType::Identifier { //} (&[TokenType::Identifier]) {
         //     if self.peek_next().token_type == TokenType::Equal {
         //         return self.assignment_stmt(reporter);
         //     }
         }
 
+        let stmt = self.simple_stmt(reporter)?;
+        self.end_of_stmt(reporter)?;
+        Ok(stmt)
+    }
+
+    // A statement that fits on one line and doesn't open a block.  These are the only statements allowed in a single-line `if`.
+    fn simple_stmt(&mut self, reporter: &mut ErrorReporter) -> Result<Stmt, ParseError> {
+        if self.match_token(&[TokenType::Break, TokenType::Continue]) {
+            return self.loop_jump_stmt(reporter);
+        } else if self.match_token(&[TokenType::Return]) {
+            return self.return_stmt(reporter);
+        }
+
         self.expr_stmt(reporter)
     }
 
-    fn print_stmt(&mut self, reporter: &mut ErrorReporter) -> Result<Stmt, ParseError> {
-        let expr = self.expression(reporter)?;
+    // Parse the rest of `if <condition> then`, up to and including the `end if` that closes the whole chain.
+    fn if_stmt(&mut self, keyword: Token, reporter: &mut ErrorReporter) -> Result<Stmt, ParseError> {
+        let condition = self.expression(reporter)?;
+        self.consume(TokenType::Then, "Expected 'then' after 'if' condition.", reporter)?;
+        if !self.check(TokenType::NewLine) && !self.check(TokenType::SemiColon) && !self.is_at_end() {
+            let stmt = self.single_line_if(keyword, condition, reporter)?;
+            self.end_of_stmt(reporter)?;
+            return Ok(stmt);
+        }
         self.end_of_stmt(reporter)?;
-        Ok(Stmt::Print(expr))
+
+        let then_branch = self.block(reporter)?;
+        let mut else_branch = Vec::new();
+        if self.match_token(&[TokenType::Else]) {
+            if self.match_token(&[TokenType::If]) {
+                // The nested `if` consumes the `end if` for the whole chain.
+                let nested = self.if_stmt(self.previous(), reporter)?;
+                let span = keyword.span.to(nested.span());
+                else_branch.push(nested);
+                return Ok(Stmt::If(condition, then_branch, else_branch, span));
+            }
+
+            self.end_of_stmt(reporter)?;
+            else_branch = self.block(reporter)?;
+        }
+
+        self.end_block(&keyword, reporter)?;
+        let span = self.span_from(&keyword.span);
+        self.end_of_stmt(reporter)?;
+        Ok(Stmt::If(condition, then_branch, else_branch, span))
+    }
+
+    // Parse the rest of `if <condition> then <statement> else <statement>` after the `then`, all on one line.
+    // An `else if` continues the chain on the same line.
+    fn single_line_if(&mut self, keyword: Token, condition: Expr, reporter: &mut ErrorReporter) -> Result<Stmt, ParseError> {
+        let then_branch = vec![self.single_line_body(reporter)?];
+        let mut else_branch = Vec::new();
+        if self.match_token(&[TokenType::Else]) {
+            if self.match_token(&[TokenType::If]) {
+                let keyword = self.previous();
+                let condition = self.expression(reporter)?;
+                self.consume(TokenType::Then, "Expected 'then' after 'if' condition.", reporter)?;
+                else_branch.push(self.single_line_if(keyword, condition, reporter)?);
+            } else {
+                else_branch.push(self.single_line_body(reporter)?);
+            }
+        }
+
+        Ok(Stmt::If(condition, then_branch, else_branch, self.span_from(&keyword.span)))
+    }
+
+    fn single_line_body(&mut self, reporter: &mut ErrorReporter) -> Result<Stmt, ParseError> {
+        if self.check(TokenType::For) || self.check(TokenType::While) {
+            return Err(self.error(self.peek(), "loop is invalid within single-line 'if'", reporter));
+        }
+
+        self.simple_stmt(reporter)
+    }
+
+    fn while_stmt(&mut self, keyword: Token, reporter: &mut ErrorReporter) -> Result<Stmt, ParseError> {
+        let condition = self.expression(reporter)?;
+        self.end_of_stmt(reporter)?;
+
+        let body = self.loop_body(reporter)?;
+        self.end_block(&keyword, reporter)?;
+        let span = self.span_from(&keyword.span);
+        self.end_of_stmt(reporter)?;
+        Ok(Stmt::While(condition, body, span))
+    }
+
+    fn for_stmt(&mut self, keyword: Token, reporter: &mut ErrorReporter) -> Result<Stmt, ParseError> {
+        self.consume(TokenType::Identifier, "Expected loop variable after 'for'.", reporter)?;
+        let variable = self.previous();
+        self.consume(TokenType::In, "Expected 'in' after loop variable.", reporter)?;
+        let sequence = self.expression(reporter)?;
+        self.end_of_stmt(reporter)?;
+
+        let body = self.loop_body(reporter)?;
+        self.end_block(&keyword, reporter)?;
+        let span = self.span_from(&keyword.span);
+        self.end_of_stmt(reporter)?;
+        Ok(Stmt::For(variable, sequence, body, span))
+    }
+
+    fn loop_body(&mut self, reporter: &mut ErrorReporter) -> Result<Vec<Stmt>, ParseError> {
+        self.loop_depth += 1;
+        let body = self.block(reporter);
+        self.loop_depth -= 1;
+        body
+    }
+
+    // `break` or `continue`, which are only allowed inside a loop.
+    fn loop_jump_stmt(&mut self, reporter: &mut ErrorReporter) -> Result<Stmt, ParseError> {
+        let keyword = self.previous();
+        if self.loop_depth == 0 {
+            let message = format!("'{}' without open loop block", keyword.lexeme);
+            return Err(self.error(keyword, &message, reporter));
+        }
+
+        match keyword.token_type {
+            TokenType::Break => Ok(Stmt::Break(keyword.span)),
+            _ => Ok(Stmt::Continue(keyword.span)),
+        }
+    }
+
+    // A `return` outside of a function is allowed; it just stops the program.
+    fn return_stmt(&mut self, reporter: &mut ErrorReporter) -> Result<Stmt, ParseError> {
+        let keyword = self.previous();
+        if self.check(TokenType::NewLine) || self.check(TokenType::SemiColon) || self.check(TokenType::Else) || self.is_at_end() {
+            return Ok(Stmt::Return(None, keyword.span));
+        }
+
+        let value = self.expression(reporter)?;
+        Ok(Stmt::Return(Some(value), self.span_from(&keyword.span)))
+    }
+
+    // Parse statements up to the `else` or `end` that closes the current block.
+    fn block(&mut self, reporter: &mut ErrorReporter) -> Result<Vec<Stmt>, ParseError> {
+        let mut stmts = Vec::new();
+
+        self.skip_empty_stmts();
+        while !self.check(TokenType::End) && !self.check(TokenType::Else) && !self.is_at_end() {
+            stmts.push(self.statement(reporter)?);
+            self.skip_empty_stmts();
+        }
+
+        Ok(stmts)
+    }
+
+    // Consume the `end <keyword>` that closes the block opened by `keyword`.
+    fn end_block(&mut self, keyword: &Token, reporter: &mut ErrorReporter) -> Result<(), ParseError> {
+        if self.is_at_end() {
+            let message = format!("'{}' without matching 'end {}'", keyword.lexeme, keyword.lexeme);
+            return Err(self.error(self.peek(), &message, reporter));
+        }
+        if self.check(TokenType::Else) {
+            return Err(self.error(self.peek(), "'else' without matching 'if'", reporter));
+        }
+        if !self.check(TokenType::End) || self.peek_next().token_type != keyword.token_type {
+            return Err(self.unmatched_end(reporter));
+        }
+
+        self.advance();
+        self.advance();
+        Ok(())
+    }
+
+    // Report an `end` that doesn't close the innermost open block.
+    fn unmatched_end(&mut self, reporter: &mut ErrorReporter) -> ParseError {
+        let keyword = self.peek_next();
+        match keyword.token_type {
+            TokenType::If | TokenType::For | TokenType::While | TokenType::Function => {
+                let message = format!("'end {}' without matching '{}'", keyword.lexeme, keyword.lexeme);
+                self.error(self.peek(), &message, reporter)
+            },
+            _ => self.error(self.peek(), "Expected 'if', 'for', 'while' or 'function' after 'end'.", reporter),
+        }
     }
 
     // fn assignment_stmt(&mut self, reporter: &mut ErrorReporter) -> Result<Stmt, ParseError> {
@@ -140,9 +317,49 @@ impl Parser {
     // }
 
     fn expr_stmt(&mut self, reporter: &mut ErrorReporter) -> Result<Stmt, ParseError> {
+        if self.check(TokenType::Identifier) {
+            if let Some(call) = self.command_call(reporter)? {
+                let span = *call.span();
+                return Ok(Stmt::Expression(call, span));
+            }
+        }
+
         let expr = self.expression(reporter)?;
-        self.end_of_stmt(reporter)?;
-        Ok(Stmt::Expression(expr))
+        let span = *expr.span();
+        Ok(Stmt::Expression(expr, span))
+    }
+
+    // A statement can call a function without parentheses around its arguments: `print "hi"`, `list.push 5`.
+    // That's only the case if a variable, index or member lookup is followed by something that can start an argument,
+    // otherwise this backs up so the statement can be parsed as an ordinary expression.
+    fn command_call(&mut self, reporter: &mut ErrorReporter) -> Result<Option<Expr>, ParseError> {
+        let start = self.current;
+        let callee = self.lookup_chain(true, reporter)?;
+        if !matches!(callee, Expr::Literal(..) | Expr::Dot(..) | Expr::Index(..)) || !self.starts_argument() {
+            self.current = start;
+            return Ok(None);
+        }
+
+        let token = self.previous();
+        let mut arguments = vec![self.expression(reporter)?];
+        while self.match_token(&[TokenType::Comma]) {
+            arguments.push(self.expression(reporter)?);
+        }
+        let span = self.span_from(callee.span());
+        Ok(Some(Expr::Call(Box::new(callee), token, arguments, span)))
+    }
+
+    fn starts_argument(&self) -> bool {
+        let token = self.peek();
+        match token.token_type {
+            TokenType::Number | TokenType::String | TokenType::Identifier | TokenType::True | TokenType::False | TokenType::Null |
+            TokenType::Not | TokenType::New | TokenType::At | TokenType::Function | TokenType::LeftBrace => true,
+            // `f [1]` and `f (1)` pass an argument, while `f[1]` and `f(1)` never get here.
+            TokenType::LeftBracket | TokenType::LeftParen => token.after_space,
+            // `f -1` passes -1, but `f - 1` and `f-1` are subtractions.
+            TokenType::Minus => token.after_space && !self.peek_next().after_space,
+            _ => false,
+        }
     }
 
     fn end_of_stmt(&mut self, reporter: &mut ErrorReporter) -> Result<(), ParseError> {
@@ -171,7 +388,7 @@ impl Parser {
         if self.match_token(&[TokenType::Equal]) {
             let operator = self.previous();
             let right = self.assignment(reporter)?;
-            expr = Expr::Binary(Box::new(expr), operator, Box::new(right));
+            expr = binary(expr, operator, right);
         }
 
         // This will group the expressions from left-to-right.
@@ -184,39 +401,75 @@ impl Parser {
         Ok(expr)
     }
 
+    // `or` binds more loosely than `and`, which binds more loosely than `not`: `a or not b and c` is
+    // `a or ((not b) and c)`.
     fn logical(&mut self, reporter: &mut ErrorReporter) -> Result<Expr, ParseError> {
-        let mut expr = self.equality(reporter)?;
+        let mut expr = self.and(reporter)?;
 
-        while self.match_token(&[TokenType::And, TokenType::Or]) {
+        while self.match_token(&[TokenType::Or]) {
           let operator = self.previous();
-          let right = self.equality(reporter)?;
-          expr = Expr::Binary(Box::new(expr), operator, Box::new(right));
+          let right = self.and(reporter)?;
+          expr = binary(expr, operator, right);
         }
     
         Ok(expr)
     }
 
-    fn equality(&mut self, reporter: &mut ErrorReporter) -> Result<Expr, ParseError> {
+    fn and(&mut self, reporter: &mut ErrorReporter) -> Result<Expr, ParseError> {
+        let mut expr = self.not(reporter)?;
+
+        while self.match_token(&[TokenType::And]) {
+          let operator = self.previous();
+          let right = self.not(reporter)?;
+          expr = binary(expr, operator, right);
+        }
+    
+        Ok(expr)
+    }
+
+    // `not` applies to a whole comparison, so `not 2 > 3` is `not (2 > 3)`.
+    fn not(&mut self, reporter: &mut ErrorReporter) -> Result<Expr, ParseError> {
+        if self.match_token(&[TokenType::Not]) {
+            let operator = self.previous();
+            let right = self.not(reporter)?;
+            let span = operator.span.to(right.span());
+            return Ok(Expr::Unary(operator, Box::new(right), span));
+        }
+
+        self.isa(reporter)
+    }
+
+    fn isa(&mut self, reporter: &mut ErrorReporter) -> Result<Expr, ParseError> {
         let mut expr = self.comparison(reporter)?;
 
-        while self.match_token(&[TokenType::BangEqual, TokenType::EqualEqual]) {
+        while self.match_token(&[TokenType::Isa]) {
           let operator = self.previous();
           let right = self.comparison(reporter)?;
-          expr = Expr::Binary(Box::new(expr), operator, Box::new(right));
+          expr = binary(expr, operator, right);
         }
     
         Ok(expr)
     }
 
+    // Equality and ordering share a level, and a run of them is a chain rather than nested comparisons:
+    // `1 == 0 < 8` means `1 == 0 and 0 < 8`.
     fn comparison(&mut self, reporter: &mut ErrorReporter) -> Result<Expr, ParseError> {
-        let mut expr = self.term(reporter)?;
+        let expr = self.term(reporter)?;
 
-        while self.match_token(&[TokenType::Greater, TokenType::GreaterEqual, TokenType::Less, TokenType::LessEqual]) {
+        let mut comparisons = Vec::new();
+        while self.match_token(&[TokenType::BangEqual, TokenType::EqualEqual, TokenType::Greater, TokenType::GreaterEqual, TokenType::Less, TokenType::LessEqual]) {
           let operator = self.previous();
           let right = self.term(reporter)?;
-          expr = Expr::Binary(Box::new(expr), operator, Box::new(right));
+          comparisons.push((operator, right));
+        }
+
+        if comparisons.len() == 1 {
+            let (operator, right) = comparisons.pop().unwrap();
+            return Ok(binary(expr, operator, right));
+        } else if comparisons.len() > 1 {
+            let span = self.span_from(expr.span());
+            return Ok(Expr::Comparison(Box::new(expr), comparisons, span));
         }
-    
         Ok(expr)
     }
 
@@ -226,7 +479,7 @@ impl Parser {
         while self.match_token(&[TokenType::Minus, TokenType::Plus]) {
           let operator = self.previous();
           let right = self.factor(reporter)?;
-          expr = Expr::Binary(Box::new(expr), operator, Box::new(right));
+          expr = binary(expr, operator, right);
         }
     
         Ok(expr)
@@ -235,40 +488,248 @@ impl Parser {
     fn factor(&mut self, reporter: &mut ErrorReporter) -> Result<Expr, ParseError> {
         let mut expr = self.unary(reporter)?;
 
-        while self.match_token(&[TokenType::Slash, TokenType::Star]) {
+        while self.match_token(&[TokenType::Slash, TokenType::Star, TokenType::Percent]) {
           let operator = self.previous();
           let right = self.unary(reporter)?;
-          expr = Expr::Binary(Box::new(expr), operator, Box::new(right));
+          expr = binary(expr, operator, right);
         }
 
         Ok(expr)
     }
 
     fn unary(&mut self, reporter: &mut ErrorReporter) -> Result<Expr, ParseError> {
-        if self.match_token(&[TokenType::Not, TokenType::Minus]) {
+        if self.match_token(&[TokenType::Minus, TokenType::New]) {
+            let operator = self.previous();
+            let right = self.unary(reporter)?;
+            let span = operator.span.to(right.span());
+            return Ok(Expr::Unary(operator, Box::new(right), span));
+        }
+
+        self.power(reporter)
+    }
+
+    // `^` binds tighter than unary minus, so `-2^2` is -4.  It groups right-to-left: `2^3^2` is `2^9`.
+    fn power(&mut self, reporter: &mut ErrorReporter) -> Result<Expr, ParseError> {
+        let expr = self.address_of(reporter)?;
+
+        if self.match_token(&[TokenType::Caret]) {
             let operator = self.previous();
             let right = self.unary(reporter)?;
-            return Ok(Expr::Unary(operator, Box::new(right)));
+            return Ok(binary(expr, operator, right));
+        }
+
+        Ok(expr)
+    }
+
+    // `@` applies to a whole lookup like `@a.b[c].d`.
+    fn address_of(&mut self, reporter: &mut ErrorReporter) -> Result<Expr, ParseError> {
+        if self.match_token(&[TokenType::At]) {
+            let operator = self.previous();
+            let right = self.call(reporter)?;
+            let span = operator.span.to(right.span());
+            return Ok(Expr::AddressOf(operator, Box::new(right), span));
         }
-      
-        self.primary(reporter)
+
+        self.call(reporter)
+    }
+
+    // Postfix operators: calls, indexing, slicing and member access.
+    fn call(&mut self, reporter: &mut ErrorReporter) -> Result<Expr, ParseError> {
+        self.lookup_chain(false, reporter)
+    }
+
+    // Calls, indexes and member lookups, like `a.b[c](d)`.
+    fn lookup_chain(&mut self, statement_start: bool, reporter: &mut ErrorReporter) -> Result<Expr, ParseError> {
+        let mut expr = self.primary(reporter)?;
+
+        loop {
+            // At the start of a statement, a space before `(` or `[` begins the arguments of a command-style call instead,
+            // so that `print (1 + 2) * 3` and `print [1, 2]` work.
+            if statement_start && self.peek().after_space && (self.check(TokenType::LeftParen) || self.check(TokenType::LeftBracket)) {
+                break;
+            } else if self.match_token(&[TokenType::LeftParen]) {
+                expr = self.finish_call(expr, reporter)?;
+            } else if self.match_token(&[TokenType::LeftBracket]) {
+                expr = self.finish_index(expr, reporter)?;
+            } else if self.match_token(&[TokenType::Dot]) {
+                self.consume(TokenType::Identifier, "Expected property name after '.'.", reporter)?;
+                let span = self.span_from(expr.span());
+                expr = Expr::Dot(Box::new(expr), self.previous(), span);
+            } else {
+                break;
+            }
+        }
+
+        Ok(expr)
+    }
+
+    fn finish_call(&mut self, callee: Expr, reporter: &mut ErrorReporter) -> Result<Expr, ParseError> {
+        let paren = self.previous();
+        let mut arguments = Vec::new();
+
+        if !self.check(TokenType::RightParen) {
+            loop {
+                arguments.push(self.expression(reporter)?);
+                if !self.match_token(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+
+        self.consume(TokenType::RightParen, "Expected ')' after arguments.", reporter)?;
+        let span = self.span_from(callee.span());
+        Ok(Expr::Call(Box::new(callee), paren, arguments, span))
+    }
+
+    // Parse the rest of `expr[index]`, `expr[start:end]`, or any of the slice forms with an omitted bound.
+    fn finish_index(&mut self, expr: Expr, reporter: &mut ErrorReporter) -> Result<Expr, ParseError> {
+        let bracket = self.previous();
+
+        if self.match_token(&[TokenType::Colon]) {
+            return self.finish_slice(expr, bracket, None, reporter);
+        }
+
+        let index = self.expression(reporter)?;
+        if self.match_token(&[TokenType::Colon]) {
+            return self.finish_slice(expr, bracket, Some(Box::new(index)), reporter);
+        }
+
+        self.consume(TokenType::RightBracket, "Expected ']' after index.", reporter)?;
+        let span = self.span_from(expr.span());
+        Ok(Expr::Index(Box::new(expr), bracket, Box::new(index), span))
+    }
+
+    fn finish_slice(&mut self, expr: Expr, bracket: Token, start: Option<Box<Expr>>, reporter: &mut ErrorReporter) -> Result<Expr, ParseError> {
+        let end = if self.check(TokenType::RightBracket) {
+            None
+        } else {
+            Some(Box::new(self.expression(reporter)?))
+        };
+
+        self.consume(TokenType::RightBracket, "Expected ']' after slice.", reporter)?;
+        let span = self.span_from(expr.span());
+        Ok(Expr::Slice(Box::new(expr), bracket, start, end, span))
     }
 
     fn primary(&mut self, reporter: &mut ErrorReporter) -> Result<Expr, ParseError> {
-        if self.match_token(&[TokenType::False, TokenType::True, TokenType::Null, TokenType::Number, TokenType::String, TokenType::Identifier]) {
-            return Ok(Expr::Literal(self.previous()));
+        if self.match_token(&[TokenType::Number, TokenType::False, TokenType::True, TokenType::Null, TokenType::String, TokenType::Identifier]) {
+            let token = self.previous();
+            let span = token.span;
+            return Ok(Expr::Literal(token, span));
         }
     
+        if self.match_token(&[TokenType::LeftBracket]) {
+            return self.list(reporter);
+        }
+
+        if self.match_token(&[TokenType::LeftBrace]) {
+            return self.map(reporter);
+        }
+
+        if self.match_token(&[TokenType::Function]) {
+            return self.function(reporter);
+        }
+
         if self.match_token(&[TokenType::LeftParen]) {
+            let paren = self.previous();
             let expr = self.expression(reporter)?;
             self.consume(TokenType::RightParen, "Expect ')' after expression.", reporter)?;
-            Ok(Expr::Grouping(Box::new(expr)))
+            Ok(Expr::Grouping(Box::new(expr), self.span_from(&paren.span)))
         } else {
             // Err(ParseError::UnexpectedToken(self.peek()))
             Err(self.error(self.peek(), "Expected a primary expression.", reporter))
         }
     }
 
+    // A list literal may span several lines and end with a trailing comma.
+    fn list(&mut self, reporter: &mut ErrorReporter) -> Result<Expr, ParseError> {
+        let bracket = self.previous();
+        let mut elements = Vec::new();
+
+        self.skip_newlines();
+        while !self.check(TokenType::RightBracket) {
+            elements.push(self.expression(reporter)?);
+            self.skip_newlines();
+            if !self.match_token(&[TokenType::Comma]) {
+                break;
+            }
+            self.skip_newlines();
+        }
+
+        self.consume(TokenType::RightBracket, "Expected ']' after list elements.", reporter)?;
+        let span = self.span_from(&bracket.span);
+        Ok(Expr::List(bracket, elements, span))
+    }
+
+    // Like a list literal, a map literal may span several lines and end with a trailing comma.
+    fn map(&mut self, reporter: &mut ErrorReporter) -> Result<Expr, ParseError> {
+        let brace = self.previous();
+        let mut entries = Vec::new();
+
+        self.skip_newlines();
+        while !self.check(TokenType::RightBrace) {
+            let key = self.expression(reporter)?;
+            self.consume(TokenType::Colon, "Expected ':' after map key.", reporter)?;
+            self.skip_newlines();
+            let value = self.expression(reporter)?;
+            entries.push((key, value));
+
+            self.skip_newlines();
+            if !self.match_token(&[TokenType::Comma]) {
+                break;
+            }
+            self.skip_newlines();
+        }
+
+        self.consume(TokenType::RightBrace, "Expected '}' after map entries.", reporter)?;
+        let span = self.span_from(&brace.span);
+        Ok(Expr::Map(brace, entries, span))
+    }
+
+    // `function(a, b=2)` through `end function`.  The parentheses may be left off when there are no parameters.
+    fn function(&mut self, reporter: &mut ErrorReporter) -> Result<Expr, ParseError> {
+        let keyword = self.previous();
+        let mut params = Vec::new();
+
+        if self.match_token(&[TokenType::LeftParen]) {
+            while !self.check(TokenType::RightParen) {
+                self.consume(TokenType::Identifier, "Expected parameter name.", reporter)?;
+                let name = self.previous();
+                let default = if self.match_token(&[TokenType::Equal]) {
+                    Some(self.expression(reporter)?)
+                } else {
+                    None
+                };
+                params.push((name, default));
+
+                if !self.match_token(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+            self.consume(TokenType::RightParen, "Expected ')' after parameters.", reporter)?;
+        }
+        self.end_of_stmt(reporter)?;
+
+        // A loop outside the function doesn't make `break` valid inside it.
+        let loop_depth = std::mem::replace(&mut self.loop_depth, 0);
+        let body = self.block(reporter);
+        self.loop_depth = loop_depth;
+        let body = body?;
+
+        self.end_block(&keyword, reporter)?;
+        let span = self.span_from(&keyword.span);
+        Ok(Expr::Function(keyword, params, Rc::new(body), span))
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.match_token(&[TokenType::NewLine]) {}
+    }
+
+    // Blank lines and stray semicolons between statements don't do anything.
+    fn skip_empty_stmts(&mut self) {
+        while self.match_token(&[TokenType::NewLine, TokenType::SemiColon]) {}
+    }
+
     fn consume(&mut self, token_type: TokenType, message: &str, reporter: &mut ErrorReporter) -> Result<(), ParseError> {
         if self.check(token_type) {
             self.advance();
@@ -282,33 +743,156 @@ impl Parser {
         reporter.error_token(token.clone(), message);
         ParseError::UnexpectedToken(token.clone())
     }
+}
 
-    // TODO: In MiniScript, it should be enough to just scan for the EOL.
-    fn synchronize(&mut self) {
-        self.advance();
-
-        while !self.is_at_end() {
-            if self.previous().token_type == TokenType::SemiColon || self.previous().token_type == TokenType::NewLine || self.is_at_end() {
-                return;
-            }
-
-            match self.peek().token_type {
-                TokenType::Class | TokenType::Fun | TokenType::Var | TokenType::For | TokenType::If | TokenType::While | TokenType::Print | TokenType::Return => return,
-                _ => (),
-            }
-
-            self.advance();
-        }
-    }
+// A binary operation covers both of its operands.
+fn binary(left: Expr, operator: Token, right: Expr) -> Expr {
+    let span = left.span().to(right.span());
+    Expr::Binary(Box::new(left), operator, Box::new(right), span)
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{error_reporter::ErrorReporter, parser::Parser, scanner::Scanner};
+    use crate::{error_reporter::ErrorReporter, parser::Parser, scanner::Scanner, statement::Stmt, test_support::capture_output, Expr, Miniscript, Span};
 
     #[test]
     fn test_parse_expressions() {
-        test_parse_expression("-123 * (45.67)", "(* (- 123) (group 45.67))")
+        test_parse_expression("-123 * (45.67)", "(* (- 123) (group 45.67))");
+        test_parse_expression("[1, 2,\n 3][0]", "(index (list 1 2 3) 0)");
+        test_parse_expression("a[1:]", "(slice a 1 _)");
+        test_parse_expression("a[:-1] = b", "(= (slice a _ (- 1)) b)");
+        test_parse_expression("{\"a\": 1,\n 2: [b]}", "(map (\"a\" 1) (2 (list b)))");
+        test_parse_expression("d.items[42].name", "(. (index (. d items) 42) name)");
+        test_parse_expression("new a isa b == c and d", "(and (isa (new a) (== b c)) d)");
+        test_parse_expression("1 or 0 and 0", "(or 1 (and 0 0))");
+        test_parse_expression("not 2 > 3 and not a isa b", "(and (not (> 2 3)) (not (isa a b)))");
+        test_parse_expression("not not -x", "(not (not (- x)))");
+        test_parse_expression("@a.b[c] == -@d", "(== (@ (index (. a b) c)) (- (@ d)))");
+        test_parse_expression("-2^3^-1 % 4 * 5", "(* (% (- (^ 2 (^ 3 (- 1)))) 4) 5)");
+        test_parse_expression("@a ^ @b", "(^ (@ a) (@ b))");
+        test_parse_expression("1 == 0 < x + 1 >= 10", "(compare 1 == 0 < (+ x 1) >= 10)");
+        test_parse_expression("print -1 and 0", "(call print (and (- 1) 0))");
+        test_parse_expression("print - 1", "(- print 1)");
+        test_parse_expression("print (1 + 2) * 3, [4]", "(call print (* (group (+ 1 2)) 3) (list 4))");
+        test_parse_expression("print(1 + 2) * 3", "(* (call print (+ 1 2)) 3)");
+        test_parse_expression("x = f (1) + a [0]", "(= x (+ (call f 1) (index a 0)))");
+        test_parse_expression("a.b[0].c \"x\", @f", "(call (. (index (. a b) 0) c) \"x\" (@ f))");
+        test_parse_expression("if a then\n print 1\nelse if b then; print 2\nelse\n c = 3\n d\nend if", "if a then (call print 1) else if b then (call print 2) else (= c 3); d end if end if");
+    }
+
+    #[test]
+    fn test_mismatched_end() {
+        test_parse_error("if 1 then\nprint 1\n", "Compiler Error: 'if' without matching 'end if' [line 4]");
+        test_parse_error("if 1 then\nprint 1\nend while", "Compiler Error: 'end while' without matching 'while' [line 3]");
+        test_parse_error("print 1\nend if", "Compiler Error: 'end if' without matching 'if' [line 2]");
+        test_parse_error("x = 2.5e+", "Compiler Error: Malformed number: 2.5e+ [line 1]");
+    }
+
+    #[test]
+    fn test_single_line_if() {
+        test_parse_expression("if a then print 1 else b = 2", "if a then (call print 1) else (= b 2) end if");
+        test_parse_expression("if a then print 1 else if b then print 2 else print 3", "if a then (call print 1) else if b then (call print 2) else (call print 3) end if end if");
+        test_parse_error("if true then for i in \"abc\"", "Compiler Error: loop is invalid within single-line 'if' [line 1]");
+        test_parse_error("if true then while true", "Compiler Error: loop is invalid within single-line 'if' [line 1]");
+    }
+
+    #[test]
+    fn test_while() {
+        test_parse_expression("while a\n if b then break\n continue\nend while", "while a if b then break end if; continue end while");
+        test_parse_error("break", "Compiler Error: 'break' without open loop block [line 1]");
+        test_parse_error("while 1\nend while\ncontinue", "Compiler Error: 'continue' without open loop block [line 3]");
+        test_parse_error("while 1\nelse\nend while", "Compiler Error: 'else' without matching 'if' [line 2]");
+    }
+
+    #[test]
+    fn test_function() {
+        test_parse_expression("f = function(a, b=2)\n return a + b\nend function", "(= f (function (a b=2) return (+ a b)))");
+        test_parse_expression("f = function\n if a then return\nend function", "(= f (function () if a then return end if))");
+        test_parse_expression("f(1, g(2))(3)", "(call (call f 1 (call g 2)) 3)");
+        test_parse_error("while 1\n f = function\n  break\n end function\nend while", "Compiler Error: 'break' without open loop block [line 3]");
+        test_parse_error("f = function\n return 1\n", "Compiler Error: 'function' without matching 'end function' [line 4]");
+        // The reference allows `return` outside of a function, so it isn't a compile error here either.
+        test_parse_expression("return 3", "return 3");
+    }
+
+    #[test]
+    fn test_for() {
+        test_parse_expression("for x in [1, 2]\n print x\n break\nend for", "for x in (list 1 2) (call print x); break end for");
+        test_parse_error("for x in y\n print x\n", "Compiler Error: 'for' without matching 'end for' [line 4]");
+        test_parse_error("for x in y\n while 1\n end for\nend while", "Compiler Error: 'end for' without matching 'for' [line 3]");
+    }
+
+    #[test]
+    fn test_unicode() {
+        test_parse_expression("größe = \"Grüße, 世界\" // ¡hola!", "(= größe \"Grüße, 世界\")");
+        test_parse_expression("print \"✓\" + 1", "(call print (+ \"✓\" 1))");
+        test_parse_error("x = 1 → 2", "Compiler Error: Unexpected character: → [line 1]");
+    }
+
+    #[test]
+    fn test_spans() {
+        let source = "x = f(1, [2, 3])  // call\nif x then\n print \"é\" + x.y[0]\nend if\nwhile x; break; end while";
+        let stmts = parse(source);
+        let text = |span: &Span| &source[span.start..span.end];
+
+        assert_eq!(text(stmts[0].span()), "x = f(1, [2, 3])");
+        let Stmt::Expression(Expr::Binary(_, _, call, _), _) = &stmts[0] else { panic!("Expected an assignment.") };
+        assert_eq!(text(call.span()), "f(1, [2, 3])");
+        assert_eq!(call.span().to_string(), "1:5-1:17");
+
+        assert_eq!(text(stmts[1].span()), "if x then\n print \"é\" + x.y[0]\nend if");
+        assert_eq!(stmts[1].span().to_string(), "2:1-4:7");
+        let Stmt::If(_, then_branch, _, _) = &stmts[1] else { panic!("Expected an if.") };
+        let Stmt::Expression(Expr::Call(_, _, arguments, _), span) = &then_branch[0] else { panic!("Expected a call.") };
+        assert_eq!(span.to_string(), "3:2-3:20");
+        assert_eq!(text(arguments[0].span()), "\"é\" + x.y[0]");
+
+        let Stmt::While(_, body, span) = &stmts[2] else { panic!("Expected a while.") };
+        assert_eq!(text(span), "while x; break; end while");
+        assert_eq!(body[0].span().to_string(), "5:10-5:15");
+
+        let stmts = parse("if a then b = (1) else return\nf = function(a)\n return -a\nend function");
+        assert_eq!(stmts[0].span().to_string(), "1:1-1:30");
+        assert_eq!(stmts[1].span().to_string(), "2:1-4:13");
+    }
+
+    fn parse(input: &str) -> Vec<Stmt> {
+        let mut reporter = ErrorReporter::new();
+        let mut scanner = Scanner::new(input);
+        scanner.scan_tokens(&mut reporter);
+        let stmts = Parser::new(scanner.tokens).parse(&mut reporter).unwrap();
+        assert!(!reporter.had_error(), "{}", input);
+        stmts
+    }
+
+    #[test]
+    fn test_first_error_only() {
+        assert_eq!(parse_errors("for i in range(0,10)\n while true\n  break\n end for\n print i\nend while"), ["Compiler Error: 'end for' without matching 'for' [line 4]"]);
+        assert_eq!(parse_errors("x = ) + 1\ny = 2\nz = ]"), ["Compiler Error: Expected a primary expression. [line 1]"]);
+        assert_eq!(parse_errors("x = 1e → 2"), ["Compiler Error: Malformed number: 1e [line 1]"]);
+    }
+
+    fn test_parse_error(input: &str, expected_error: &str) {
+        assert_eq!(parse_errors(input), [expected_error]);
+    }
+
+    // Every compile error reported for `input`, in order.  Like a real run, a scanning error stops the parse.
+    fn parse_errors(input: &str) -> Vec<String> {
+        let mut reporter = ErrorReporter::new();
+
+        let mut scanner = Scanner::new(input);
+        scanner.scan_tokens(&mut reporter);
+
+        if !reporter.had_error() {
+            let _ = Parser::new(scanner.tokens).parse(&mut reporter);
+        }
+        assert!(reporter.had_error());
+
+        let mut miniscript = Miniscript::new();
+        let output = capture_output(&mut miniscript);
+        reporter.dump(&mut miniscript.context);
+
+        output.take()
     }
 
     fn test_parse_expression(input: &str, expected_output: &str) {
@@ -1,37 +1,60 @@
-use crate::{error_reporter::ErrorReporter, token::Token, token_type::TokenType};
+use std::str::CharIndices;
 
-pub struct Scanner {
-    source: String,
+use crate::{error_reporter::ErrorReporter, span::Span, token::Token, token_type::TokenType};
+
+// The scanner walks the source once, front to back, so the time it takes grows linearly with the size of the script.
+pub struct Scanner<'a> {
+    source: &'a str,
     pub tokens: Vec<Token>,
 
-    start: i64,
-    current: i64,
+    // The cursor sits on the next character to be scanned.  Looking ahead reads past it without moving it.
+    chars: CharIndices<'a>,
+    // The byte offset where the current lexeme begins.
+    start: usize,
     line: i64,
+    // The column of the cursor, and the line and column where the current lexeme begins.
+    column: usize,
+    start_line: i64,
+    start_column: usize,
 }
 
-impl Scanner {
-    pub fn new(source: &str) -> Scanner {
+impl<'a> Scanner<'a> {
+    pub fn new(source: &'a str) -> Scanner<'a> {
         Self {
-            source: source.to_string(),
+            source,
             tokens: Vec::new(),
+            chars: source.char_indices(),
             start: 0,
-            current: 0,
             line: 1,
+            column: 1,
+            start_line: 1,
+            start_column: 1,
         }
     }
 
+    // Scanning stops at the first error.
     pub fn scan_tokens(&mut self, reporter: &mut ErrorReporter) {
-        while !self.is_at_end() {
+        while !self.is_at_end() && !reporter.had_error() {
             // We are at the beginning of the next lexeme.
-            self.start = self.current;
+            self.start = self.current();
+            self.start_line = self.line;
+            self.start_column = self.column;
             self.scan_token(reporter);
         }
 
-        self.tokens.push(Token::new(TokenType::EOF, "", self.line));
+        self.start = self.current();
+        self.start_line = self.line;
+        self.start_column = self.column;
+        self.add_token(TokenType::EOF);
     }
 
     fn is_at_end(&self) -> bool {
-        self.current >= self.source.len() as i64
+        self.chars.as_str().is_empty()
+    }
+
+    // The byte offset of the cursor.
+    fn current(&self) -> usize {
+        self.source.len() - self.chars.as_str().len()
     }
 
     fn scan_token(&mut self, reporter: &mut ErrorReporter) {
@@ -43,11 +66,17 @@ impl Scanner {
             ')' => self.add_token(TokenType::RightParen),
             '{' => self.add_token(TokenType::LeftBrace),
             '}' => self.add_token(TokenType::RightBrace),
+            '[' => self.add_token(TokenType::LeftBracket),
+            ']' => self.add_token(TokenType::RightBracket),
+            ':' => self.add_token(TokenType::Colon),
             ',' => self.add_token(TokenType::Comma),
             '-' => self.add_token(TokenType::Minus),
             '+' => self.add_token(TokenType::Plus),
             ';' => self.add_token(TokenType::SemiColon),
             '*' => self.add_token(TokenType::Star),
+            '@' => self.add_token(TokenType::At),
+            '^' => self.add_token(TokenType::Caret),
+            '%' => self.add_token(TokenType::Percent),
             
             // Match the potential multi-character operators.
             '=' => if self.match_char('=') {
@@ -80,23 +109,21 @@ impl Scanner {
             '"' => self.string(reporter),
 
             // Match numbers.
-            '0' | '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' => self.number(),
+            '0' | '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' => self.number(reporter),
 
             '.' => if self.peek().is_ascii_digit() {
-                self.number()
+                self.number(reporter)
             } else {
                 self.add_token(TokenType::Dot)
             },
 
-            '!' => if self.peek() == '=' {
+            '!' if self.peek() == '=' => {
                 self.advance();
                 self.add_token(TokenType::BangEqual);
-            } else {
-                reporter.error_line(self.line, format!("Unexpected character: {}", c).as_str());
-            }
+            },
 
-            // Match identifiers and keywords.
-            'a'..='z' | 'A'..='Z' | '_' => self.identifier(),
+            // Match identifiers and keywords.  Like the rest of the source, they may contain any Unicode letter.
+            c if c.is_alphabetic() || c == '_' => self.identifier(),
 
             // Ignore the whitespace.
             ' ' | '\r' | '\t' => {}
@@ -119,22 +146,24 @@ impl Scanner {
             self.advance();
         }
 
-        let text = &self.source[self.start as usize..self.current as usize];
+        let text = &self.source[self.start..self.current()];
         let token_type = match text {
             "and" => TokenType::And,
-            "class" => TokenType::Class,
+            "break" => TokenType::Break,
+            "continue" => TokenType::Continue,
             "else" => TokenType::Else,
             "false" => TokenType::False,
             "for" => TokenType::For,
-            "fun" => TokenType::Fun,
+            "function" => TokenType::Function,
             "if" => TokenType::If,
+            "in" => TokenType::In,
+            "isa" => TokenType::Isa,
+            "new" => TokenType::New,
             "null" => TokenType::Null,
             "not" => TokenType::Not,
             "or" => TokenType::Or,
-            "print" => TokenType::Print,
             "return" => TokenType::Return,
-            "super" => TokenType::Super,
-            "this" => TokenType::This,
+            "then" => TokenType::Then,
             "true" => TokenType::True,
             "var" => TokenType::Var,
             "while" => TokenType::While,
@@ -171,7 +200,7 @@ impl Scanner {
           self.add_token(TokenType::String);
     }
 
-    fn number(&mut self) {
+    fn number(&mut self, reporter: &mut ErrorReporter) {
         if self.peek() != '.' {
             while self.peek().is_ascii_digit() {
                 self.advance();
@@ -188,43 +217,129 @@ impl Scanner {
             }
         }
 
-        self.add_token(TokenType::Number);
+        // Look for an exponent, like the "e-3" in "2.5e-3".
+        if self.peek() == 'e' || self.peek() == 'E' {
+            self.advance();
+            if self.peek() == '+' || self.peek() == '-' {
+                self.advance();
+            }
+            while self.peek().is_ascii_digit() {
+                self.advance();
+            }
+        }
+
+        // This is the only place a number is checked, so the parser and evaluator can use its value as it is.
+        let lexeme = &self.source[self.start..self.current()];
+        match lexeme.parse() {
+            Ok(number) => {
+                self.add_token(TokenType::Number);
+                self.tokens.last_mut().unwrap().number = Some(number);
+            },
+            Err(_) => {
+                reporter.error_line(self.line, format!("Malformed number: {}", lexeme).as_str());
+            },
+        }
     }
 
     fn peek(&self) -> char {
-        if self.is_at_end() {
-            return '\0';
-        }
-        self.source.chars().nth(self.current as usize).unwrap()
+        self.chars.as_str().chars().next().unwrap_or('\0')
     }
 
     fn peek_next(&self) -> char {
-        if self.current + 1 >= self.source.len() as i64 {
-            return '\0';
-        }
-        self.source.chars().nth((self.current + 1) as usize).unwrap()
+        self.chars.as_str().chars().nth(1).unwrap_or('\0')
     }
 
     fn advance(&mut self) -> char {
-        let c = self.source.chars().nth(self.current as usize).unwrap();
-        self.current += 1;
+        let c = self.chars.next().map_or('\0', |(_, c)| c);
+        self.column = if c == '\n' { 1 } else { self.column + 1 };
         c
     }
 
     fn match_char(&mut self, expected: char) -> bool {
-        if self.is_at_end() {
-            return false;
-        }
-        if self.source.chars().nth(self.current as usize).unwrap() != expected {
+        if self.is_at_end() || self.peek() != expected {
             return false;
         }
 
-        self.current += 1;
+        self.advance();
         true
     }
 
     fn add_token(&mut self, token_type: TokenType) {
-        let text = &self.source[self.start as usize..self.current as usize];
-        self.tokens.push(Token::new(token_type, text, self.line));
+        let text = &self.source[self.start..self.current()];
+        let mut token = Token::new(token_type, text, self.line);
+        token.span = Span {
+            start: self.start,
+            end: self.current(),
+            line: self.start_line,
+            column: self.start_column,
+            end_line: self.line,
+            end_column: self.column,
+        };
+        token.after_space = self.source[..self.start].ends_with([' ', '\t']);
+        self.tokens.push(token);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    #[test]
+    fn test_scan_time_is_linear() {
+        // About 1.7 million characters.  A linear scan takes well under a second even in a debug build, while one that
+        // walks the source from the start for every character would take hours.
+        let source = data_script(20_000);
+        let started = Instant::now();
+        let mut scanner = Scanner::new(&source);
+        scanner.scan_tokens(&mut ErrorReporter::new());
+        assert!(started.elapsed() < Duration::from_secs(10), "Scanning {} bytes took {:?}", source.len(), started.elapsed());
+    }
+
+    #[test]
+    fn test_scan_large_script() {
+        let source = data_script(5_000);
+        let mut reporter = ErrorReporter::new();
+        let mut scanner = Scanner::new(&source);
+        scanner.scan_tokens(&mut reporter);
+
+        assert!(!reporter.had_error());
+        // Each line is `items.push {"id": 1, "name": "Größe 1", "tags": ["a", "b"]} // ¡generado!` plus a newline.
+        assert_eq!(scanner.tokens.len(), 5_000 * 21 + 1);
+        assert_eq!(scanner.tokens.last().unwrap().line, 5_001);
+    }
+
+    #[test]
+    fn test_numbers() {
+        let mut scanner = Scanner::new("42 .5 1e3 2.5E-1 7e+2");
+        scanner.scan_tokens(&mut ErrorReporter::new());
+        let numbers: Vec<Option<f64>> = scanner.tokens.iter().map(|token| token.number).collect();
+        assert_eq!(numbers, [Some(42.0), Some(0.5), Some(1000.0), Some(0.25), Some(700.0), None]);
+
+        let mut reporter = ErrorReporter::new();
+        let mut scanner = Scanner::new("x = 1e");
+        scanner.scan_tokens(&mut reporter);
+        assert!(reporter.had_error());
+        assert_eq!(scanner.tokens.len(), 3);
+    }
+
+    #[test]
+    fn test_token_spans() {
+        let source = "é = \"a\nb\"\nf(1)";
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens(&mut ErrorReporter::new());
+        let spans: Vec<String> = scanner.tokens.iter().map(|token| format!("{} {}..{} {}", token.span, token.span.start, token.span.end, token.line)).collect();
+
+        // Columns count characters, while offsets count bytes.  A string keeps the line it ends on as its `line`.
+        assert_eq!(spans, [
+            "1:1-1:2 0..2 1", "1:3-1:4 3..4 1", "1:5-2:3 5..10 2", "2:3-3:1 10..11 3",
+            "3:1-3:2 11..12 3", "3:2-3:3 12..13 3", "3:3-3:4 13..14 3", "3:4-3:5 14..15 3", "3:5-3:5 15..15 3",
+        ]);
+    }
+
+    // The kind of generated data script that gets large: one line per record.
+    fn data_script(lines: usize) -> String {
+        (1..=lines).map(|i| format!("items.push {{\"id\": {i}, \"name\": \"Größe {i}\", \"tags\": [\"a\", \"b\"]}} // ¡generado!\n")).collect()
     }
 }
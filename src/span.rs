@@ -0,0 +1,35 @@
+use std::fmt::{self, Display, Formatter};
+
+// Where a token or syntax node sits in the source.  Offsets are in bytes, with `end` just past the last byte, so
+// `&source[span.start..span.end]` is the text itself.  Lines and columns count from 1, and columns count characters
+// rather than bytes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: i64,
+    pub column: usize,
+    // The position just past the last character.
+    pub end_line: i64,
+    pub end_column: usize,
+}
+
+impl Span {
+    // The span that runs from the start of `self` to the end of `other`.
+    pub fn to(&self, other: &Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end,
+            line: self.line,
+            column: self.column,
+            end_line: other.end_line,
+            end_column: other.end_column,
+        }
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}-{}:{}", self.line, self.column, self.end_line, self.end_column)
+    }
+}
@@ -1,28 +1,56 @@
 use std::fmt::{Debug, Display};
 
-use crate::Expr;
+use crate::{Expr, Span, Token};
 
+// Like expressions, every statement ends with its span.  A block statement's span runs through its `end`.
 #[derive(Clone, PartialEq)]
 pub enum Stmt {
-    Expression(Expr),
-    Print(Expr),
+    Expression(Expr, Span),
+    // `else if` chains are nested inside the else branch.
+    If(Expr, Vec<Stmt>, Vec<Stmt>, Span),
+    While(Expr, Vec<Stmt>, Span),
+    For(Token, Expr, Vec<Stmt>, Span),
+    Break(Span),
+    Continue(Span),
+    Return(Option<Expr>, Span),
     // Assignment(String, Expr),
 }
 
-// impl Stmt {
-//     pub fn line(&self) -> i64 {
-//         match self {
-//             Stmt::Expression(expr) => expr.line(),
-//             Stmt::Print(expr) => expr.line(),
-//         }
-//     }
-// }
+impl Stmt {
+    pub fn span(&self) -> &Span {
+        match self {
+            Stmt::Expression(_, span)
+            | Stmt::If(_, _, _, span)
+            | Stmt::While(_, _, span)
+            | Stmt::For(_, _, _, span)
+            | Stmt::Break(span)
+            | Stmt::Continue(span)
+            | Stmt::Return(_, span) => span,
+        }
+    }
+}
+
+fn format_block(stmts: &[Stmt]) -> String {
+    stmts.iter().map(|stmt| stmt.to_string()).collect::<Vec<String>>().join("; ")
+}
 
 impl Display for Stmt {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Stmt::Expression(expr) => write!(f, "{}", expr),
-            Stmt::Print(expr) => write!(f, "print {}", expr),
+            Stmt::Expression(expr, _) => write!(f, "{}", expr),
+            Stmt::If(condition, then_branch, else_branch, _) => {
+                write!(f, "if {} then {}", condition, format_block(then_branch))?;
+                if !else_branch.is_empty() {
+                    write!(f, " else {}", format_block(else_branch))?;
+                }
+                write!(f, " end if")
+            },
+            Stmt::While(condition, body, _) => write!(f, "while {} {} end while", condition, format_block(body)),
+            Stmt::For(variable, sequence, body, _) => write!(f, "for {} in {} {} end for", variable.lexeme, sequence, format_block(body)),
+            Stmt::Break(_) => write!(f, "break"),
+            Stmt::Continue(_) => write!(f, "continue"),
+            Stmt::Return(Some(expr), _) => write!(f, "return {}", expr),
+            Stmt::Return(None, _) => write!(f, "return"),
             // Stmt::Assignment(name, expr) => write!(f, "{} = {}", name, expr),
         }
     }
@@ -31,8 +59,14 @@ impl Display for Stmt {
 impl Debug for Stmt {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Stmt::Expression(expr) => write!(f, "Expression({})", expr),
-            Stmt::Print(expr) => write!(f, "Print({})", expr),
+            Stmt::Expression(expr, _) => write!(f, "Expression({})", expr),
+            Stmt::If(condition, then_branch, else_branch, _) => write!(f, "If({}, {:?}, {:?})", condition, then_branch, else_branch),
+            Stmt::While(condition, body, _) => write!(f, "While({}, {:?})", condition, body),
+            Stmt::For(variable, sequence, body, _) => write!(f, "For({}, {}, {:?})", variable.lexeme, sequence, body),
+            Stmt::Break(_) => write!(f, "Break"),
+            Stmt::Continue(_) => write!(f, "Continue"),
+            Stmt::Return(Some(expr), _) => write!(f, "Return({})", expr),
+            Stmt::Return(None, _) => write!(f, "Return"),
             // Stmt::Assignment(name, expr) => write!(f, "Assignment({}, {})", name, expr),
         }
     }
@@ -0,0 +1,27 @@
+// Output capture shared by the unit tests and the integration tests.
+
+use std::{cell::RefCell, rc::Rc};
+
+use crate::Miniscript;
+
+// Sends everything `miniscript` prints to the returned buffer, one entry per line.
+pub fn capture_output(miniscript: &mut Miniscript) -> Rc<RefCell<Vec<String>>> {
+    let output = Rc::new(RefCell::new(Vec::new()));
+    let sink = output.clone();
+    miniscript.set_output(move |line| sink.borrow_mut().push(line.to_string()));
+    output
+}
+
+// Runs `code` in `miniscript` and returns what it printed.
+pub fn run_in(miniscript: &mut Miniscript, code: &str) -> Vec<String> {
+    let output = capture_output(miniscript);
+    miniscript.run(code);
+    output.take()
+}
+
+// Runs `code` in a fresh interpreter.
+pub fn run(code: &str) -> (Miniscript, Vec<String>) {
+    let mut miniscript = Miniscript::new();
+    let output = run_in(&mut miniscript, code);
+    (miniscript, output)
+}
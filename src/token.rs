@@ -1,12 +1,19 @@
 use std::fmt::Debug;
 
-use crate::token_type::TokenType;
+use crate::{span::Span, token_type::TokenType};
 
 #[derive(Clone, PartialEq)]
 pub struct Token {
     pub token_type: TokenType,
     pub lexeme: String,
     pub line: i64,
+    // Exactly where the lexeme is.  For a string that runs over several lines, `line` is the line it ends on, while the
+    // span starts on the line it begins on.
+    pub span: Span,
+    // The value of a number literal, worked out when it's scanned.
+    pub number: Option<f64>,
+    // Whether there was a space or tab right before the token.  `f (x)` and `f(x)` can mean different things.
+    pub after_space: bool,
 }
 
 impl Token {
@@ -15,13 +22,16 @@ impl Token {
             token_type,
             lexeme: lexeme.to_string(),
             line,
+            span: Span::default(),
+            number: None,
+            after_space: false,
         }
     }
 }
 
 impl std::fmt::Display for Token {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        if self.lexeme.trim().len() > 0 {
+        if !self.lexeme.trim().is_empty() {
             write!(f, "{:?} {}", self.token_type, self.lexeme)
         } else {
             write!(f, "{:?}", self.token_type)
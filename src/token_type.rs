@@ -1,8 +1,8 @@
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum TokenType {
     // Single-character tokens.
-    LeftParen, RightParen, LeftBrace, RightBrace,
-    Comma, Dot, Minus, Plus, Slash, Star,
+    LeftParen, RightParen, LeftBrace, RightBrace, LeftBracket, RightBracket,
+    At, Caret, Colon, Comma, Dot, Minus, Percent, Plus, Slash, Star,
     SemiColon, NewLine, // Both of these are used to separate statements.
 
     // One or two character tokens.
@@ -15,13 +15,10 @@ pub enum TokenType {
     Identifier, String, Number,
 
     // Keywords.
-    Class, // TODO: Remove this once you understand how the class infrastructure works.
-    Fun, // TODO: Rename to Function after implementing.
-    This, // TODO: Rename to Self after implementing.
+    Function,
     Var, // TODO: Remove the necessity of this one after implementing variable declarations.
-    Print, // TODO: Replace this with some type of intrinsic function.
     True, False, // TODO: I really like the idea of these being runtime constants.
-    And, Else, For, If, Not, Null, Or, Return, Super, While, End,
+    And, Break, Continue, Else, For, If, In, Isa, New, Not, Null, Or, Return, Then, While, End,
 
     EOF,
 }
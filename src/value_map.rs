@@ -0,0 +1,139 @@
+// A map from MiniScript values to MiniScript values.
+// Any value can be a key.  Keys are compared with MiniScript equality, so two lists with the same elements
+// are the same key.  Entries are kept in insertion order so that maps print and iterate predictably.
+
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::EvalResult;
+
+// A shared handle to a map, as held by a map value.
+pub type MapRef = Rc<RefCell<ValueMap>>;
+
+#[derive(Debug, Clone, Default)]
+pub struct ValueMap {
+    // Each entry keeps the hash code its key had when it was stored.
+    entries: Vec<(EvalResult, EvalResult, u64)>,
+    // The positions of the entries, by the hash code of their key.  Keys with the same hash code share a bucket.
+    positions: HashMap<u64, Vec<usize>>,
+}
+
+// Where a key is, or would go, in a map.
+// Finding a key hashes and compares it, which borrows any lists and maps inside it.  A key can contain the very map
+// it's stored in, so the slot has to be found before that map is borrowed for the update.
+pub struct Slot {
+    hash: u64,
+    position: Option<usize>,
+}
+
+impl ValueMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn slot(&self, key: &EvalResult) -> Slot {
+        let hash = key.hash_code();
+        let position = self.positions.get(&hash)
+            .and_then(|bucket| bucket.iter().copied().find(|position| self.entries[*position].0 == *key));
+        Slot { hash, position }
+    }
+
+    pub fn get(&self, key: &EvalResult) -> Option<&EvalResult> {
+        let position = self.slot(key).position?;
+        Some(&self.entries[position].1)
+    }
+
+    pub fn contains_key(&self, key: &EvalResult) -> bool {
+        self.slot(key).position.is_some()
+    }
+
+    pub fn set(&mut self, key: EvalResult, value: EvalResult) {
+        let slot = self.slot(&key);
+        self.set_at(slot, key, value);
+    }
+
+    // Set the entry in `slot`, which must have been found for `key` in this map as it is now.
+    pub fn set_at(&mut self, slot: Slot, key: EvalResult, value: EvalResult) {
+        match slot.position {
+            Some(position) => self.entries[position].1 = value,
+            None => {
+                self.positions.entry(slot.hash).or_default().push(self.entries.len());
+                self.entries.push((key, value, slot.hash));
+            },
+        }
+    }
+
+    // Replace the value of the entry at `index` in insertion order.
+    pub fn set_value_at(&mut self, index: usize, value: EvalResult) {
+        self.entries[index].1 = value;
+    }
+
+    pub fn remove(&mut self, key: &EvalResult) -> Option<EvalResult> {
+        let slot = self.slot(key);
+        self.remove_at(slot)
+    }
+
+    // Remove the entry in `slot`, which must have been found in this map as it is now.
+    pub fn remove_at(&mut self, slot: Slot) -> Option<EvalResult> {
+        let (_, value) = self.remove_position(slot.position?);
+        Some(value)
+    }
+
+    // Remove the first entry in insertion order.  This never looks at the key, so it works even if the key has
+    // changed since it was stored.
+    pub fn remove_first(&mut self) -> Option<(EvalResult, EvalResult)> {
+        (!self.entries.is_empty()).then(|| self.remove_position(0))
+    }
+
+    fn remove_position(&mut self, position: usize) -> (EvalResult, EvalResult) {
+        let (key, value, hash) = self.entries.remove(position);
+        if let Some(bucket) = self.positions.get_mut(&hash) {
+            bucket.retain(|index| *index != position);
+            if bucket.is_empty() {
+                self.positions.remove(&hash);
+            }
+        }
+
+        // Everything after the removed entry moved down by one.
+        for index in self.positions.values_mut().flatten() {
+            if *index > position {
+                *index -= 1;
+            }
+        }
+
+        (key, value)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.positions.clear();
+    }
+
+    // The entry at `index` in insertion order.
+    pub fn entry_at(&self, index: usize) -> Option<(&EvalResult, &EvalResult)> {
+        self.entries.get(index).map(|(key, value, _)| (key, value))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&EvalResult, &EvalResult)> {
+        self.entries.iter().map(|(key, value, _)| (key, value))
+    }
+}
+
+// Set `key` in a shared map.  The key may contain the map itself.
+pub fn set_entry(map: &MapRef, key: EvalResult, value: EvalResult) {
+    let slot = map.borrow().slot(&key);
+    map.borrow_mut().set_at(slot, key, value);
+}
+
+// Remove `key` from a shared map.  The key may contain the map itself.
+pub fn remove_entry(map: &MapRef, key: &EvalResult) -> Option<EvalResult> {
+    let slot = map.borrow().slot(key);
+    map.borrow_mut().remove_at(slot)
+}
@@ -0,0 +1,43 @@
+// Host applications adding their own functions through `Miniscript::add_intrinsic`.
+
+use miniscript::{EvalResult, Miniscript, Param};
+
+#[allow(dead_code)]
+#[path = "../src/test_support.rs"]
+mod test_support;
+
+use test_support::run_in;
+
+fn with_scale() -> Miniscript {
+    let mut miniscript = Miniscript::new();
+    miniscript.add_intrinsic("scale", vec![Param::new("x", EvalResult::Null), Param::new("factor", EvalResult::Number(2.0))], |_, arguments| {
+        match (&arguments[0], &arguments[1]) {
+            (EvalResult::Number(x), EvalResult::Number(factor)) => Ok(EvalResult::Number(x * factor)),
+            _ => Err("scale needs numbers".to_string()),
+        }
+    });
+    miniscript
+}
+
+#[test]
+fn test_call_intrinsic() {
+    let mut miniscript = with_scale();
+    assert_eq!(run_in(&mut miniscript, "print scale(21)\nprint scale(2, 5)\nf = @scale\nprint f(4)"), vec!["42", "10", "8"]);
+}
+
+#[test]
+fn test_intrinsic_errors() {
+    let mut miniscript = with_scale();
+    assert_eq!(run_in(&mut miniscript, "scale \"x\""), vec!["Runtime Error: scale needs numbers [line 1]"]);
+    assert_eq!(run_in(&mut miniscript, "scale 1, 2, 3"), vec!["Runtime Error: Too Many Arguments [line 1]"]);
+}
+
+#[test]
+fn test_intrinsic_output() {
+    let mut miniscript = Miniscript::new();
+    miniscript.add_intrinsic("shout", vec![Param::new("s", EvalResult::String("hey".to_string()))], |context, arguments| {
+        context.print(&arguments[0].to_string().to_uppercase());
+        Ok(EvalResult::Null)
+    });
+    assert_eq!(run_in(&mut miniscript, "shout\nshout \"hi\"\nshout = 1\nprint shout"), vec!["HEY", "HI", "1"]);
+}
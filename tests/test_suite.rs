@@ -0,0 +1,191 @@
+// Runs the reference conformance suites (`TestSuite.txt` and `OwnTestSuite.txt`) against `Miniscript`.
+//
+// Each case is a block of source code followed by the output it is expected to produce:
+//
+//     ======================================================================
+//     ==== Title of the case.
+//     print "Hello world!"
+//     ----------------------------------------------------------------------
+//     Hello world!
+//
+// Not every case passes yet, so the test doesn't fail on the first mismatch.  It fails when fewer cases pass than
+// `BASELINE`, which should be raised whenever a change gets more of them passing.
+// Run with `cargo test --test test_suite -- --nocapture` to see the report.  Set `SUITE_DIFF=1` to also print
+// the expected and actual output of every failing case.
+
+use std::{cell::RefCell, rc::Rc, sync::mpsc, thread, time::Duration};
+
+use miniscript::Miniscript;
+
+// A runaway loop shouldn't hang the whole suite.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+// How many cases, across both suites, are known to pass.
+const BASELINE: usize = 188;
+
+#[derive(Debug, Default)]
+struct TestCase {
+    title: String,
+    line: usize,
+    source: Vec<String>,
+    expected: Vec<String>,
+}
+
+enum Outcome {
+    Pass,
+    Fail(Vec<String>),
+    Panic,
+    Timeout,
+}
+
+fn parse_suite(text: &str) -> Vec<TestCase> {
+    let mut cases = Vec::new();
+    let mut case: Option<TestCase> = None;
+    let mut in_expected = false;
+
+    for (index, line) in text.lines().enumerate() {
+        if line.starts_with("====") {
+            // Either a separator or a title; both end the previous case once its output has started.
+            if in_expected {
+                cases.extend(case.take());
+                in_expected = false;
+            }
+            let case = case.get_or_insert_with(|| TestCase { line: index + 1, ..Default::default() });
+            let title = line.trim_start_matches('=').trim();
+            if case.title.is_empty() && !title.is_empty() {
+                case.title = title.to_string();
+            }
+        } else if line.starts_with("----") {
+            in_expected = true;
+        } else if let Some(case) = case.as_mut() {
+            if in_expected {
+                case.expected.push(line.to_string());
+            } else {
+                case.source.push(line.to_string());
+            }
+        }
+    }
+    if in_expected {
+        cases.extend(case);
+    }
+
+    cases
+}
+
+fn run_case(case: &TestCase) -> Outcome {
+    let source = case.source.join("\n");
+    let (sender, receiver) = mpsc::channel();
+
+    let spawned = thread::Builder::new().spawn(move || {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let sink = output.clone();
+
+        let mut miniscript = Miniscript::new();
+        // `print ""` outputs an empty line, which `lines()` would drop entirely.
+        miniscript.set_output(move |line| sink.borrow_mut().extend(line.split('\n').map(str::to_string)));
+        miniscript.run(&source);
+
+        let lines = output.borrow().clone();
+        let _ = sender.send(lines);
+    });
+    if spawned.is_err() {
+        return Outcome::Panic;
+    }
+
+    match receiver.recv_timeout(TIMEOUT) {
+        Ok(actual) => {
+            if trim_trailing_blank_lines(&actual) == trim_trailing_blank_lines(&case.expected) {
+                Outcome::Pass
+            } else {
+                Outcome::Fail(actual)
+            }
+        },
+        Err(mpsc::RecvTimeoutError::Timeout) => Outcome::Timeout,
+        Err(mpsc::RecvTimeoutError::Disconnected) => Outcome::Panic,
+    }
+}
+
+fn trim_trailing_blank_lines(lines: &[String]) -> Vec<&str> {
+    let mut lines: Vec<&str> = lines.iter().map(|line| line.trim_end()).collect();
+    while lines.last() == Some(&"") {
+        lines.pop();
+    }
+    lines
+}
+
+fn run_suite(path: &str) -> (usize, usize) {
+    let text = std::fs::read_to_string(path).unwrap_or_else(|e| panic!("Failed to read {}: {}", path, e));
+    let cases = parse_suite(&text);
+    let show_diff = std::env::var("SUITE_DIFF").is_ok_and(|v| v == "1");
+
+    // Panics inside a case are expected to be reported, not printed.
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(|_| {}));
+
+    let mut passed = 0;
+    println!("{}:", path);
+    for case in &cases {
+        let outcome = run_case(case);
+        let status = match outcome {
+            Outcome::Pass => "PASS",
+            Outcome::Fail(_) => "FAIL",
+            Outcome::Panic => "PANIC",
+            Outcome::Timeout => "TIMEOUT",
+        };
+        println!("  [{}] line {}: {}", status, case.line, case.title);
+
+        match outcome {
+            Outcome::Pass => passed += 1,
+            Outcome::Fail(actual) if show_diff => {
+                println!("    expected:");
+                case.expected.iter().for_each(|line| println!("      {}", line));
+                println!("    actual:");
+                actual.iter().for_each(|line| println!("      {}", line));
+            },
+            _ => {},
+        }
+    }
+
+    std::panic::set_hook(default_hook);
+    println!("{}: {}/{} passed", path, passed, cases.len());
+    (passed, cases.len())
+}
+
+#[test]
+fn test_suite_parsing() {
+    let cases = parse_suite("\
+======================================================================
+==== First case.
+==== (continued title)
+print 1
+----------------------------------------------------------------------
+1
+====
+print 2
+----------------------------------------------------------------------
+2
+======================================================================
+");
+
+    assert_eq!(cases.len(), 2);
+    assert_eq!(cases[0].title, "First case.");
+    assert_eq!(cases[0].source, vec!["print 1"]);
+    assert_eq!(cases[0].expected, vec!["1"]);
+    assert_eq!(cases[1].title, "");
+    assert_eq!(cases[1].source, vec!["print 2"]);
+    assert_eq!(cases[1].expected, vec!["2"]);
+}
+
+#[test]
+fn test_conformance_suites() {
+    let mut passed = 0;
+    let mut total = 0;
+    for suite in ["TestSuite.txt", "OwnTestSuite.txt"] {
+        let (suite_passed, suite_total) = run_suite(&format!("{}/{}", env!("CARGO_MANIFEST_DIR"), suite));
+        assert!(suite_total > 0, "No test cases found in {}.", suite);
+        passed += suite_passed;
+        total += suite_total;
+    }
+    println!("Total: {}/{} passed", passed, total);
+    assert!(passed >= BASELINE, "Only {} cases passed, down from {}.", passed, BASELINE);
+}